@@ -0,0 +1,304 @@
+#![allow(dead_code)]
+
+use crate::lgca::{cell, Cell, Grid};
+
+/// Plane indices, in the order of `cell::DIRECTIONS` followed by the boundary flag.
+const UP: usize = 0;
+const RIGHT: usize = 1;
+const DOWN: usize = 2;
+const LEFT: usize = 3;
+const BOUNDARY: usize = 4;
+
+/// The bit of `Cell` stored in each plane.
+const PLANE_BITS: [Cell; 5] = [
+    cell::UP,
+    cell::RIGHT,
+    cell::DOWN,
+    cell::LEFT,
+    cell::BOUNDARY,
+];
+
+/// A grid stored as five bit-planes, one per direction plus one for the boundary flag, instead
+/// of one packed `Cell` per cell. Streaming a plane is then a whole-word shift, and collisions
+/// are bitwise logic on 64 cells at once. Each row starts on a fresh `u64`, bit `x % 64` of
+/// word `x / 64`; bits past `width` are always zero.
+///
+/// `propagate_bitplane` gives exactly the same result as `propagate_grid` with the packed
+/// `Grid`, and is many times faster: `--benchmark` in a release build with this feature times
+/// the two side by side. It covers only the plain HPP step: sources, sponges, tracers and the
+/// other per-cell features still need a `Grid`, via `from_grid` and `to_grid`.
+pub struct BitPlaneGrid {
+    pub planes: [Vec<u64>; 5],
+    pub width: usize,
+    pub height: usize,
+    words_per_row: usize,
+}
+
+impl BitPlaneGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        let words_per_row = width.div_ceil(64);
+        let words = words_per_row
+            .checked_mul(height)
+            .expect("grid dimensions overflow usize");
+        Self {
+            planes: std::array::from_fn(|_| vec![0; words]),
+            width,
+            height,
+            words_per_row,
+        }
+    }
+
+    pub fn from_grid(grid: &Grid) -> Self {
+        let mut planes = Self::new(grid.width, grid.height);
+        for y in 0..grid.height as isize {
+            for x in 0..grid.width as isize {
+                planes.set(x, y, grid.get(x, y));
+            }
+        }
+        planes
+    }
+
+    pub fn to_grid(&self) -> Grid {
+        let mut grid = Grid::new(self.width, self.height);
+        for y in 0..self.height as isize {
+            for x in 0..self.width as isize {
+                grid.set(x, y, self.get(x, y));
+            }
+        }
+        grid
+    }
+
+    /// Word index and bit mask of an in-range cell.
+    fn bit(&self, x: usize, y: usize) -> (usize, u64) {
+        (y * self.words_per_row + x / 64, 1 << (x % 64))
+    }
+
+    /// The cell at `(x, y)`, or boundary outside the grid, as for `Grid::get`.
+    pub fn get(&self, x: isize, y: isize) -> Cell {
+        if (x < 0) || (x as usize >= self.width) || (y < 0) || (y as usize >= self.height) {
+            return cell::BOUNDARY;
+        }
+        let (word, mask) = self.bit(x as usize, y as usize);
+        self.planes
+            .iter()
+            .zip(PLANE_BITS)
+            .filter(|(plane, _)| plane[word] & mask != 0)
+            .fold(cell::EMPTY, |value, (_, bit)| value | bit)
+    }
+
+    pub fn set(&mut self, x: isize, y: isize, value: Cell) {
+        assert!(
+            (x >= 0) && ((x as usize) < self.width) && (y >= 0) && ((y as usize) < self.height)
+        );
+        let (word, mask) = self.bit(x as usize, y as usize);
+        for (plane, bit) in self.planes.iter_mut().zip(PLANE_BITS) {
+            if value & bit != 0 {
+                plane[word] |= mask;
+            } else {
+                plane[word] &= !mask;
+            }
+        }
+    }
+
+    /// Exchanges cell data with `other`, for double buffering.
+    pub fn swap_buffers(&mut self, other: &mut BitPlaneGrid) {
+        debug_assert_eq!(
+            (self.width, self.height),
+            (other.width, other.height),
+            "swapped grids must have the same dimensions"
+        );
+        std::mem::swap(&mut self.planes, &mut other.planes);
+    }
+
+    /// Number of particles in all cells, as for `Grid::total_particles`.
+    pub fn total_particles(&self) -> u64 {
+        self.planes[..BOUNDARY]
+            .iter()
+            .flatten()
+            .map(|word| word.count_ones() as u64)
+            .sum()
+    }
+
+    /// Mask of the bits of the last word of a row that lie inside the grid.
+    fn last_word_mask(&self) -> u64 {
+        match self.width % 64 {
+            0 => u64::MAX,
+            bits => (1 << bits) - 1,
+        }
+    }
+
+    /// `dst(x, y) = src(x - 1, y)`, zero in the first column.
+    fn shift_right(&self, src: &[u64], dst: &mut [u64]) {
+        let last_word_mask = self.last_word_mask();
+        for (src_row, dst_row) in src
+            .chunks(self.words_per_row.max(1))
+            .zip(dst.chunks_mut(self.words_per_row.max(1)))
+        {
+            let mut carry = 0;
+            for (&word, out) in src_row.iter().zip(dst_row.iter_mut()) {
+                *out = word << 1 | carry;
+                carry = word >> 63;
+            }
+            if let Some(last) = dst_row.last_mut() {
+                *last &= last_word_mask;
+            }
+        }
+    }
+
+    /// `dst(x, y) = src(x + 1, y)`, zero in the last column.
+    fn shift_left(&self, src: &[u64], dst: &mut [u64]) {
+        for (src_row, dst_row) in src
+            .chunks(self.words_per_row.max(1))
+            .zip(dst.chunks_mut(self.words_per_row.max(1)))
+        {
+            let mut carry = 0;
+            for (&word, out) in src_row.iter().zip(dst_row.iter_mut()).rev() {
+                *out = word >> 1 | carry << 63;
+                carry = word & 1;
+            }
+        }
+    }
+
+    /// `dst(x, y) = src(x, y - 1)`, zero in the bottom row.
+    fn shift_up(&self, src: &[u64], dst: &mut [u64]) {
+        let row = self.words_per_row.min(dst.len());
+        dst[..row].fill(0);
+        dst[row..].copy_from_slice(&src[..src.len() - row]);
+    }
+
+    /// `dst(x, y) = src(x, y + 1)`, zero in the top row.
+    fn shift_down(&self, src: &[u64], dst: &mut [u64]) {
+        let row = self.words_per_row.min(dst.len());
+        let rest = dst.len() - row;
+        dst[..rest].copy_from_slice(&src[row..]);
+        dst[rest..].fill(0);
+    }
+}
+
+/// The streaming half of a step over bit-planes: each direction plane shifts one cell along
+/// its direction, as `stream` does for the packed grid, and particles leaving across an edge
+/// come back turned round. Boundary cells stay boundary.
+pub fn stream_bitplane(grid: &BitPlaneGrid, next_grid: &mut BitPlaneGrid) {
+    debug_assert_eq!(
+        (grid.width, grid.height),
+        (next_grid.width, next_grid.height),
+        "next_grid dimensions must match grid"
+    );
+    let [up, right, down, left, boundary] = &mut next_grid.planes;
+    grid.shift_up(&grid.planes[UP], up);
+    grid.shift_right(&grid.planes[RIGHT], right);
+    grid.shift_down(&grid.planes[DOWN], down);
+    grid.shift_left(&grid.planes[LEFT], left);
+    boundary.copy_from_slice(&grid.planes[BOUNDARY]);
+
+    // Bounce back off the edges, as `Grid::neighbors` does.
+    let row = grid.words_per_row;
+    if row == 0 || grid.height == 0 {
+        return;
+    }
+    let top = (grid.height - 1) * row;
+    for word in 0..row {
+        down[top + word] |= grid.planes[UP][top + word];
+        up[word] |= grid.planes[DOWN][word];
+    }
+    let last_bit = 1 << ((grid.width - 1) % 64);
+    for start in (0..grid.height).map(|y| y * row) {
+        right[start] |= grid.planes[LEFT][start] & 1;
+        left[start + row - 1] |= grid.planes[RIGHT][start + row - 1] & last_bit;
+    }
+}
+
+/// The collision half of a step over bit-planes, in place on a streamed grid, as `collide` does
+/// for the packed grid: head-on pairs in fluid cells scatter and particles in boundary cells
+/// reflect off the wall following `reflect_at_wall`.
+pub fn collide_bitplane(grid: &mut BitPlaneGrid) {
+    let words = grid.planes[BOUNDARY].len();
+    let last_word_mask = grid.last_word_mask();
+    let fluid: Vec<u64> = grid.planes[BOUNDARY]
+        .iter()
+        .enumerate()
+        .map(|(index, &boundary)| {
+            let in_grid = if (index + 1) % grid.words_per_row.max(1) == 0 {
+                last_word_mask
+            } else {
+                u64::MAX
+            };
+            !boundary & in_grid
+        })
+        .collect();
+    // Whether the neighbour in each direction is fluid; cells past the edge are not.
+    let mut fluid_up = vec![0; words];
+    let mut fluid_right = vec![0; words];
+    let mut fluid_down = vec![0; words];
+    let mut fluid_left = vec![0; words];
+    grid.shift_down(&fluid, &mut fluid_up);
+    grid.shift_left(&fluid, &mut fluid_right);
+    grid.shift_up(&fluid, &mut fluid_down);
+    grid.shift_right(&fluid, &mut fluid_left);
+
+    let [up, right, down, left, boundary] = &mut grid.planes;
+    for index in 0..words {
+        let (u, r, d, l, b) = (
+            up[index],
+            right[index],
+            down[index],
+            left[index],
+            boundary[index],
+        );
+        // Fluid cells: exactly one head-on pair flips to the other axis.
+        let scatter = (u & d & !r & !l) | (r & l & !u & !d);
+        // Boundary cells: reverse the components along axes with a fluid neighbour, or every
+        // component when there is none.
+        let vertical = fluid_up[index] | fluid_down[index];
+        let horizontal = fluid_right[index] | fluid_left[index];
+        let enclosed = !(vertical | horizontal);
+        let vertical = vertical | enclosed;
+        let horizontal = horizontal | enclosed;
+        let pick = |fluid: u64, wall: u64| (b & wall) | (!b & fluid);
+        up[index] = pick(u ^ scatter, (d & vertical) | (u & !vertical));
+        down[index] = pick(d ^ scatter, (u & vertical) | (d & !vertical));
+        right[index] = pick(r ^ scatter, (l & horizontal) | (r & !horizontal));
+        left[index] = pick(l ^ scatter, (r & horizontal) | (l & !horizontal));
+    }
+}
+
+/// One full HPP step over bit-planes, the counterpart of `propagate_grid`.
+pub fn propagate_bitplane(grid: &BitPlaneGrid, next_grid: &mut BitPlaneGrid) {
+    stream_bitplane(grid, next_grid);
+    collide_bitplane(next_grid);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lgca::{find_first_divergence, Colouring, Config, Region, TraversalOrder};
+
+    #[test]
+    fn bitplane_steps_match_the_packed_grid() {
+        // 100 columns: one full word and one partial word per row. Without the edge wall,
+        // particles bounce off the edge of the grid itself.
+        for edge_wall in [true, false] {
+            let config = Config::new(100, 40, 1, 1, 1, Colouring::DensityBW);
+            let mut grid = Grid::new(config.width, config.height);
+            grid.fill(Region::new(0, 0, config.width, config.height), 0.4);
+            if edge_wall {
+                grid.set_boundary_at_edge(&config, 1);
+            }
+            grid.fill_boundary_circle(50.0, 20.0, 8.0);
+            grid.set_boundary(Region::new(62, 0, 3, 30));
+            assert_eq!(BitPlaneGrid::from_grid(&grid).to_grid().grid, grid.grid);
+
+            let packed = |grid: &Grid, next: &mut Grid| {
+                crate::propagate_grid(grid, next, &TraversalOrder::RowMajor)
+            };
+            let planes = |grid: &Grid, next: &mut Grid| {
+                let mut next_planes = BitPlaneGrid::new(grid.width, grid.height);
+                propagate_bitplane(&BitPlaneGrid::from_grid(grid), &mut next_planes);
+                *next = next_planes.to_grid();
+            };
+            let copy = Grid::from_raw(config.width, config.height, grid.grid.clone()).unwrap();
+            let divergence = find_first_divergence(grid, copy, 100, packed, planes);
+            assert!(divergence.is_none(), "{:?}", divergence);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use crate::lgca::Grid;
+
+/// Saved state from which a run can be resumed.
+///
+/// On disk: the magic bytes `LGCA`, then `width`, `height`, `step` and `frame` as
+/// little-endian `u64`s, then the cells in `y * width + x` order.
+pub struct Checkpoint {
+    pub step: usize,
+    /// Index of the last frame written, so numbering continues after a resume.
+    pub frame: usize,
+    pub grid: Grid,
+}
+
+const MAGIC: &[u8; 4] = b"LGCA";
+
+/// Bytes before the cells: the magic bytes and four `u64`s.
+const HEADER_BYTES: u64 = 4 + 4 * 8;
+
+/// Writes a checkpoint of `grid` at the given step and last-written frame.
+pub fn save_checkpoint(filename: &str, step: usize, frame: usize, grid: &Grid) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writer.write_all(MAGIC)?;
+    for value in [grid.width, grid.height, step, frame] {
+        writer.write_all(&(value as u64).to_le_bytes())?;
+    }
+    writer.write_all(&grid.grid)?;
+    writer.flush()
+}
+
+impl Checkpoint {
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let file = File::open(filename)?;
+        let file_bytes = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a checkpoint", filename),
+            ));
+        }
+        let mut header = [0; 4];
+        for value in header.iter_mut() {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            *value = usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is too large for this platform", filename),
+                )
+            })?;
+        }
+        let [width, height, step, frame] = header;
+        // Check the dimensions against the file before allocating for them, so a corrupt
+        // header can't ask for an enormous buffer.
+        let cells = width
+            .checked_mul(height)
+            .filter(|&cells| Some(cells as u64) == file_bytes.checked_sub(HEADER_BYTES))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} claims a {}x{} grid but holds {} bytes of cells",
+                        filename,
+                        width,
+                        height,
+                        file_bytes.saturating_sub(HEADER_BYTES)
+                    ),
+                )
+            })?;
+        let mut data = Vec::with_capacity(cells);
+        reader.read_to_end(&mut data)?;
+        let grid = Grid::from_raw(width, height, data)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        Ok(Self { step, frame, grid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoints_round_trip_and_reject_bad_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.bin");
+        let filename = path.to_str().unwrap();
+        let mut grid = Grid::new(5, 3);
+        grid.set(4, 2, crate::lgca::cell::FULL);
+        save_checkpoint(filename, 12, 3, &grid).unwrap();
+        let checkpoint = Checkpoint::load(filename).unwrap();
+        assert_eq!((checkpoint.step, checkpoint.frame), (12, 3));
+        assert_eq!(checkpoint.grid.grid, grid.grid);
+
+        // A header claiming a huge grid over the same 15 cells.
+        let mut bytes = std::fs::read(filename).unwrap();
+        bytes[4..12].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        std::fs::write(filename, &bytes).unwrap();
+        let error = Checkpoint::load(filename).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(Checkpoint::load(&format!("{}.missing", filename)).is_err());
+    }
+}
@@ -1,165 +1,1339 @@
 use std::{
-    f64::consts::PI,
-    fs::File,
-    io::{stdout, BufWriter, Write},
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Instant,
 };
 
+use rand::Rng;
+
+#[cfg(feature = "bitplane")]
+mod bitplane;
+mod checkpoint;
 mod lgca;
+mod moore;
+mod obstacle;
+mod output;
+mod render;
+mod resample;
+mod scene;
+mod simulation;
+mod species;
+mod spectrum;
+mod tracer;
 use lgca::*;
+use output::OutputSink;
+use simulation::Simulation;
+
+fn propagate_grid(grid: &Grid, next_grid: &mut Grid, order: &TraversalOrder) {
+    debug_assert_eq!(
+        grid.width, next_grid.width,
+        "next_grid width must match grid"
+    );
+    debug_assert_eq!(
+        grid.height, next_grid.height,
+        "next_grid height must match grid"
+    );
+    match order {
+        TraversalOrder::RowMajor => {
+            for y in 0..grid.height as isize {
+                for x in 0..grid.width as isize {
+                    propagate_cell(grid, next_grid, x, y);
+                }
+            }
+        }
+        TraversalOrder::ColumnMajor => {
+            for x in 0..grid.width as isize {
+                for y in 0..grid.height as isize {
+                    propagate_cell(grid, next_grid, x, y);
+                }
+            }
+        }
+    }
+}
+
+/// One step with `BoundaryMode::Periodic`: particles leaving one edge of the grid enter at
+/// the opposite edge.
+fn propagate_periodic(grid: &Grid, next_grid: &mut Grid) {
+    debug_assert_eq!(
+        (grid.width, grid.height),
+        (next_grid.width, next_grid.height),
+        "next_grid dimensions must match grid"
+    );
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            next_grid.set(x, y, next_cell_periodic(grid, x, y));
+        }
+    }
+}
+
+/// One step with `BoundaryMode::Edges`: the neighbours past each edge follow its condition.
+fn propagate_edges(grid: &Grid, next_grid: &mut Grid, edges: &EdgeConditions) {
+    debug_assert_eq!(
+        (grid.width, grid.height),
+        (next_grid.width, next_grid.height),
+        "next_grid dimensions must match grid"
+    );
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            next_grid.set(x, y, next_cell_edges(grid, x, y, edges));
+        }
+    }
+}
+
+/// `propagate_grid` (or `propagate_periodic` or `propagate_edges`, with that `rule`) with the
+/// rows stepped in parallel; the result is identical.
+#[cfg(feature = "rayon")]
+fn par_propagate_grid(
+    grid: &Grid,
+    next_grid: &mut Grid,
+    rule: impl Fn(&Grid, isize, isize) -> Cell + Sync,
+) {
+    use rayon::prelude::*;
+    debug_assert_eq!(
+        (grid.width, grid.height),
+        (next_grid.width, next_grid.height),
+        "next_grid dimensions must match grid"
+    );
+    next_grid
+        .grid
+        .par_chunks_mut(grid.width.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, value) in row.iter_mut().enumerate() {
+                *value = rule(grid, x as isize, y as isize);
+            }
+        });
+}
+
+/// One step in the way `config.execution_mode` and `config.boundary_mode` ask for.
+#[cfg(feature = "rayon")]
+fn propagate(grid: &Grid, next_grid: &mut Grid, config: &lgca::Config) {
+    match config.execution_mode {
+        lgca::ExecutionMode::Serial => propagate_serial(grid, next_grid, config),
+        lgca::ExecutionMode::Parallel => match config.boundary_mode {
+            BoundaryMode::Closed => par_propagate_grid(grid, next_grid, next_cell),
+            BoundaryMode::Periodic => par_propagate_grid(grid, next_grid, next_cell_periodic),
+            BoundaryMode::Edges(edges) => par_propagate_grid(grid, next_grid, |grid, x, y| {
+                next_cell_edges(grid, x, y, &edges)
+            }),
+        },
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn propagate(grid: &Grid, next_grid: &mut Grid, config: &lgca::Config) {
+    propagate_serial(grid, next_grid, config)
+}
+
+fn propagate_serial(grid: &Grid, next_grid: &mut Grid, config: &lgca::Config) {
+    match config.boundary_mode {
+        BoundaryMode::Closed => propagate_grid(grid, next_grid, &config.traversal_order),
+        BoundaryMode::Periodic => propagate_periodic(grid, next_grid),
+        BoundaryMode::Edges(edges) => propagate_edges(grid, next_grid, &edges),
+    }
+}
+
+/// `propagate_serial` on a single grid, for `Config::in_place_propagation`. The result is
+/// identical, but only three rows of scratch are needed instead of a second grid.
+fn propagate_inplace(grid: &mut Grid, config: &lgca::Config) {
+    match config.boundary_mode {
+        BoundaryMode::Closed => step_rows_in_place(grid, next_cell),
+        BoundaryMode::Periodic => step_rows_in_place(grid, next_cell_periodic),
+        BoundaryMode::Edges(edges) => {
+            step_rows_in_place(grid, |grid, x, y| next_cell_edges(grid, x, y, &edges))
+        }
+    }
+}
+
+/// Steps `grid` in place with `rule`, bottom row first. A row's next state is held back until
+/// the row above it has been stepped, as that still reads its old state, so `rule` only ever
+/// sees old cells. The bottom row is held back to the very end, as the top row reads it across
+/// a periodic edge.
+fn step_rows_in_place(grid: &mut Grid, rule: impl Fn(&Grid, isize, isize) -> Cell) {
+    let (width, height) = (grid.width, grid.height);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let step_row = |grid: &Grid, y: usize, row: &mut [Cell]| {
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = rule(grid, x as isize, y as isize);
+        }
+    };
+    let mut bottom = vec![lgca::cell::EMPTY; width];
+    let mut below = vec![lgca::cell::EMPTY; width];
+    let mut current = vec![lgca::cell::EMPTY; width];
+    step_row(grid, 0, &mut bottom);
+    for y in 1..height {
+        step_row(grid, y, &mut current);
+        if y >= 2 {
+            grid.grid[(y - 1) * width..y * width].copy_from_slice(&below);
+        }
+        std::mem::swap(&mut below, &mut current);
+    }
+    if height >= 2 {
+        grid.grid[(height - 1) * width..].copy_from_slice(&below);
+    }
+    grid.grid[..width].copy_from_slice(&bottom);
+}
+
+/// The streaming half of a step on its own: every particle moves one cell along its direction
+/// into `next_grid`, and boundary cells stay boundary, but nothing collides yet. With
+/// `collide`, this is the textbook two-pass form of `propagate_grid`. The fused form is about
+/// 1.7x faster (1024x1024 grid, release build), so it stays what `Simulation` runs.
+#[allow(dead_code)]
+fn stream(grid: &Grid, next_grid: &mut Grid) {
+    debug_assert_eq!(
+        grid.width, next_grid.width,
+        "next_grid width must match grid"
+    );
+    debug_assert_eq!(
+        grid.height, next_grid.height,
+        "next_grid height must match grid"
+    );
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            let wall = grid.get(x, y) & lgca::cell::BOUNDARY;
+            next_grid.set(x, y, gather_incoming(grid, x, y) | wall);
+        }
+    }
+}
+
+/// The collision half of a step on its own, in place on a streamed grid: head-on pairs in
+/// fluid cells scatter and particles in boundary cells reflect off the wall.
+#[allow(dead_code)]
+fn collide(grid: &mut Grid) {
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            let value = grid.get(x, y);
+            let next_state = if !value.is_boundary() {
+                resolve_collisions(value)
+            } else {
+                let directions = value & lgca::cell::FULL;
+                reflect_at_wall(directions, fluid_neighbours(grid, x, y)) | lgca::cell::BOUNDARY
+            };
+            grid.set(x, y, next_state);
+        }
+    }
+}
+
+/// The particles that stream into `(x, y)` this step, before collisions.
+fn gather_incoming(grid: &Grid, x: isize, y: isize) -> Cell {
+    incoming_from(grid.neighbors(x, y))
+}
+
+/// The particles that stream in from `neighbours`, in the order of `Grid::neighbors`.
+fn incoming_from([up, right, down, left]: [Cell; 4]) -> Cell {
+    // A particle arriving from the neighbour above is moving down, and so on.
+    (up & lgca::cell::DOWN)
+        | (right & lgca::cell::LEFT)
+        | (down & lgca::cell::UP)
+        | (left & lgca::cell::RIGHT)
+}
 
-fn propagate_grid(grid: &Grid, next_grid: &mut Grid) {
+/// Direction bits of the neighbours of `(x, y)` that are fluid rather than boundary.
+fn fluid_neighbours(grid: &Grid, x: isize, y: isize) -> Cell {
+    fluid_from(grid.neighbors(x, y))
+}
+
+/// Direction bits of `neighbours`, in the order of `Grid::neighbors`, that are fluid.
+fn fluid_from(neighbours: [Cell; 4]) -> Cell {
+    let mut fluid = lgca::cell::EMPTY;
+    for (direction, neighbour) in Direction::ALL.into_iter().zip(neighbours) {
+        if !neighbour.is_boundary() {
+            fluid |= direction.to_mask();
+        }
+    }
+    fluid
+}
+
+fn propagate_cell(grid: &Grid, next_grid: &mut Grid, x: isize, y: isize) {
+    next_grid.set(x, y, next_cell(grid, x, y));
+}
+
+/// The state of `(x, y)` after one step: the particles streaming in, collided.
+fn next_cell(grid: &Grid, x: isize, y: isize) -> Cell {
+    step_cell(grid.get(x, y), grid.neighbors(x, y))
+}
+
+/// `next_cell` with the neighbours across each edge taken from the opposite edge.
+fn next_cell_periodic(grid: &Grid, x: isize, y: isize) -> Cell {
+    step_cell(grid.get(x, y), grid.neighbors_periodic(x, y))
+}
+
+/// `next_cell` with the neighbours across each edge following its condition in `edges`.
+fn next_cell_edges(grid: &Grid, x: isize, y: isize, edges: &EdgeConditions) -> Cell {
+    step_cell(grid.get(x, y), grid.neighbors_with_edges(x, y, edges))
+}
+
+/// The state of a cell holding `value` after one step, given its `neighbours` in the order of
+/// `Grid::neighbors`.
+fn step_cell(value: Cell, neighbours: [Cell; 4]) -> Cell {
+    let incoming = incoming_from(neighbours);
+    if !value.is_boundary() {
+        resolve_collisions(incoming)
+    } else {
+        reflect_at_wall(incoming, fluid_from(neighbours)) | lgca::cell::BOUNDARY
+    }
+}
+
+/// Net momentum `(right - left, up - down)` carried by a cell's particles.
+fn cell_momentum(value: Cell) -> (i64, i64) {
+    let has = |direction| value.has(direction) as i64;
+    let x = has(Direction::Right) - has(Direction::Left);
+    let y = has(Direction::Up) - has(Direction::Down);
+    (x, y)
+}
+
+/// Total momentum `(px, py)` of the particles in fluid cells, with +x right and +y up.
+/// With the `rayon` feature, the simulation uses `Grid::par_momentum` instead.
+fn total_momentum(grid: &Grid) -> (i64, i64) {
+    grid.grid
+        .iter()
+        .filter(|value| !value.is_boundary())
+        .map(|&value| cell_momentum(value))
+        .fold((0, 0), |total, momentum| {
+            (total.0 + momentum.0, total.1 + momentum.1)
+        })
+}
+
+/// Force exerted on all boundary cells during the step from `grid` to `next_grid`, by momentum
+/// exchange: at every boundary cell, the momentum particles brought in minus the momentum they
+/// leave with. Returned as `(fx, fy)` in lattice units (momentum per step), with +x right and
+/// +y up. The domain-edge walls are included.
+fn boundary_force(grid: &Grid, next_grid: &Grid) -> (i64, i64) {
+    let mut force = (0, 0);
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            if !grid.get(x, y).is_boundary() {
+                continue;
+            }
+            let incoming = cell_momentum(gather_incoming(grid, x, y));
+            let outgoing = cell_momentum(next_grid.get(x, y));
+            force.0 += incoming.0 - outgoing.0;
+            force.1 += incoming.1 - outgoing.1;
+        }
+    }
+    force
+}
+
+/// Number of fluid cells in which a head-on pair scatters during the step from `grid`.
+fn collision_count(grid: &Grid) -> usize {
+    let mut collisions = 0;
     for y in 0..grid.height as isize {
         for x in 0..grid.width as isize {
-            let up = grid.get(x, y + 1) & lgca::cell::DOWN;
-            let right = grid.get(x + 1, y) & lgca::cell::LEFT;
-            let down = grid.get(x, y - 1) & lgca::cell::UP;
-            let left = grid.get(x - 1, y) & lgca::cell::RIGHT;
-            let mut next_state = up | right | down | left | (grid.get(x, y) & lgca::cell::BOUNDARY);
-            next_state = resolve_collisions(next_state);
-            next_grid.set(x, y, next_state);
+            let incoming = gather_incoming(grid, x, y);
+            if !grid.get(x, y).is_boundary() && resolve_collisions(incoming) != incoming {
+                collisions += 1;
+            }
         }
     }
+    collisions
 }
 
+/// Collides the particles in a cell. Boundary cells reverse every particle; `propagate_cell`
+/// uses `reflect_at_wall` instead where it knows the wall's orientation.
 fn resolve_collisions(cell_value: u8) -> u8 {
-    if cell_value & lgca::cell::BOUNDARY == 0 {
+    const VERTICAL_PAIR: Cell = Direction::Up.to_mask() | Direction::Down.to_mask();
+    const HORIZONTAL_PAIR: Cell = Direction::Right.to_mask() | Direction::Left.to_mask();
+    if !cell_value.is_boundary() {
         match cell_value {
-            0b0101 => 0b1010,
-            0b1010 => 0b0101,
+            HORIZONTAL_PAIR => VERTICAL_PAIR,
+            VERTICAL_PAIR => HORIZONTAL_PAIR,
             other => other,
         }
     } else {
-        let up = cell_value & lgca::cell::UP;
-        let right = cell_value & lgca::cell::RIGHT;
-        let down = cell_value & lgca::cell::DOWN;
-        let left = cell_value & lgca::cell::LEFT;
-        up >> 2 | right >> 2 | down << 2 | left << 2 | lgca::cell::BOUNDARY
+        reflect_at_wall(cell_value & lgca::cell::FULL, lgca::cell::EMPTY) | lgca::cell::BOUNDARY
     }
 }
 
-fn block_colour_density_bw(block_x: usize, block_y: usize, grid: &Grid, config: &Config) -> RGB8 {
-    let block = lgca::Block::new(block_x, block_y, config.downscale, grid);
-    if block.boundary > 0 {
-        lgca::RGB8::BOUNDARY
-    } else {
-        let val = (63 * block.total_particles() / (config.downscale * config.downscale)) as u8;
-        lgca::RGB8::new(val, val, val)
+/// Reflects the particles in a boundary cell off the wall. The wall normal is taken from
+/// `fluid_neighbours`: the velocity component along an axis with a fluid neighbour is reversed
+/// and the tangential component kept, so a top wall sends UP to DOWN but leaves LEFT moving
+/// left. Convex corners (fluid on both axes) reverse both components. With no fluid
+/// neighbour, inside a solid or in a concave corner, every particle reverses.
+///
+/// On clean walls this is exactly bounce-back: a particle only streams into a wall cell from a
+/// fluid neighbour along that neighbour's axis, which is always a normal, so it is reversed
+/// either way. The two differ only for particles already moving along the wall inside it,
+/// from walls laid over existing particles (`fill_additive` over a wall, `Grid::from_raw`, a
+/// checkpoint): those keep sliding along the wall rather than bouncing back out.
+fn reflect_at_wall(directions: Cell, fluid_neighbours: Cell) -> Cell {
+    const VERTICAL: Cell = lgca::cell::UP | lgca::cell::DOWN;
+    const HORIZONTAL: Cell = lgca::cell::RIGHT | lgca::cell::LEFT;
+    let mut normal = lgca::cell::EMPTY;
+    if fluid_neighbours & VERTICAL != 0 {
+        normal |= VERTICAL;
     }
+    if fluid_neighbours & HORIZONTAL != 0 {
+        normal |= HORIZONTAL;
+    }
+    if normal == lgca::cell::EMPTY {
+        normal = lgca::cell::FULL;
+    }
+    let reflected = directions & normal;
+    lgca::cell::opposite(reflected) | (directions & !normal)
 }
 
-fn block_colour_velocity_rgb(block_x: usize, block_y: usize, grid: &Grid, config: &Config) -> RGB8 {
-    let block = lgca::Block::new(block_x, block_y, config.downscale, grid);
-    if block.boundary > 0 {
-        lgca::RGB8::BOUNDARY
-    } else {
-        let x: f64 =
-            (block.right as f64 - block.left as f64) / (config.downscale * config.downscale) as f64;
-        let y: f64 = (block.up as f64 - block.down as f64) / (config.downscale * config.downscale) as f64;
-        let speed = f64::powf(f64::sqrt((x * x) + (y * y))/f64::sqrt(2.0), 1.0/3.0);
-        let mut angle = f64::atan2(x, y);
-        angle = if angle < 0.0 {
-            angle + 2.0*PI
-        } else {
-            angle
+/// Rewrites every source region, returning the net number of particles added (negative when
+/// a source, e.g. a sink at density 0, removes more than it injects).
+fn update_sources(grid: &mut lgca::Grid, sources: &[lgca::Source], rng: &mut impl Rng) -> i64 {
+    let mut delta = 0;
+    for source in sources {
+        delta += match &source.injection {
+            Injection::Random => grid.fill_with_rng(source.region, source.density, rng),
+            Injection::Additive => grid.fill_additive(source.region, source.density, rng),
+            Injection::Deterministic { direction } => {
+                grid.fill_pattern(source.region, source.density, *direction)
+            }
+            Injection::Profiled {
+                velocity: (vx, vy),
+                profile,
+            } => {
+                let region = source.region;
+                let mut delta = 0;
+                for row in 0..region.height {
+                    let t = (row as f64 + 0.5) / region.height as f64;
+                    let scale = profile.at(t);
+                    let row_region =
+                        Region::new(region.x, region.y + row as isize, region.width, 1);
+                    delta += grid.fill_equilibrium(
+                        row_region,
+                        source.density,
+                        scale * vx,
+                        scale * vy,
+                        rng,
+                    );
+                }
+                delta
+            }
         };
-        angle = 180.0*angle/PI;
-        // println!("speed: {} \n angle: {}", speed, angle);
-        lgca::RGB8::from_hsvf64(angle, speed, speed)
     }
+    delta
 }
 
-fn generate_rgb_sequence(grid: &Grid, config: &Config) -> Vec<u8> {
-    let mut out = Vec::<u8>::new();
-    for block_x in 0..grid.width / config.downscale {
-        for block_y in 0..grid.height / config.downscale {
-            let block_colour = match config.colouring {
-                Colouring::DensityBW => block_colour_density_bw(block_x, block_y, grid, config),
-                Colouring::VelocityColour => {
-                    block_colour_velocity_rgb(block_x, block_y, grid, config)
-                }
-            };
-            out.extend(block_colour.as_array());
-        }
+fn apply_sponges(grid: &mut lgca::Grid, sponges: &[lgca::Sponge], rng: &mut impl Rng) {
+    for sponge in sponges {
+        grid.apply_sponge(sponge.region(), sponge.strength, rng);
     }
-    out
 }
 
-fn save_grid_as_image(grid: &Grid, config: &Config, filename: &str) {
-    let file = File::create(filename).unwrap(); // TODO handle error
-    let writer = &mut BufWriter::new(file);
-    let mut encoder = png::Encoder::new(
-        writer,
-        (grid.width / config.downscale) as u32,
-        (grid.height / config.downscale) as u32,
-    );
-    encoder.set_color(png::ColorType::Rgb);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut writer = encoder
-        .write_header()
-        .expect("Failed to create image writer");
-    let image_data = generate_rgb_sequence(grid, config);
-    writer
-        .write_image_data(&image_data)
-        .expect(format!("Failed to write to {}", filename).as_str());
-}
-
-fn update_sources(grid: &mut lgca::Grid, sources: &[lgca::Source]) {
-    for source in sources {
-        grid.fill_region(
-            source.x,
-            source.y,
-            source.width,
-            source.height,
-            source.density,
-        );
+/// Wall-clock bookkeeping for a run, for the time estimate and adaptive frameskip.
+struct RunClock {
+    start_time: Instant,
+    /// The step the run (re)started from.
+    start_step: usize,
+    last_frame: Instant,
+    last_frame_step: usize,
+}
+
+impl RunClock {
+    fn new(start_step: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            start_time: now,
+            start_step,
+            last_frame: now,
+            last_frame_step: start_step,
+        }
     }
 }
 
+/// Advances one step, writes a frame when `frameskip` says one is due, samples the
+/// diagnostics every `diagnostics_stride` steps and a checkpoint every `checkpoint_interval`
+/// steps, and reports progress.
 fn tick(
-    config: &lgca::Config,
-    grid_a: &mut lgca::Grid,
-    grid_b: &mut lgca::Grid,
-    sources: &Vec<lgca::Source>,
-    start_time: Instant,
-    i: usize,
-) {
-    update_sources(grid_a, sources);
-    propagate_grid(grid_a, grid_b);
-    std::mem::swap(grid_a, grid_b);
-    if i % config.frameskip == 0 {
-        save_grid_as_image(
-            &grid_a,
-            config,
-            &format!("output/image{}.png", i / config.frameskip),
-        );
+    simulation: &mut Simulation,
+    sinks: &mut [Box<dyn OutputSink>],
+    clock: &mut RunClock,
+) -> io::Result<()> {
+    simulation.advance();
+    let i = simulation.step;
+    let since_last_frame = clock.last_frame.elapsed().as_secs_f64();
+    if simulation.config.frameskip.is_due(i, since_last_frame) {
+        clock.last_frame = Instant::now();
+        clock.last_frame_step = i;
+        write_frame(simulation, sinks)?;
+    }
+    if let Some(stride) = simulation.config.diagnostics_stride {
+        if i.is_multiple_of(stride) {
+            simulation.record_diagnostics();
+        }
+    }
+    if let Some(interval) = simulation.config.checkpoint_interval {
+        if i.is_multiple_of(interval) {
+            simulation.save_checkpoint(&simulation.config.output_path("checkpoint.bin"))?;
+        }
+    }
+    let config = &simulation.config;
+    if !config.show_progress {
+        return Ok(());
     }
     let iterations_remaining = config.iterations - i + 1;
-    let iterations_per_second = i as f64 / start_time.elapsed().as_secs_f64();
+    let iterations_per_second =
+        (i - clock.start_step) as f64 / clock.start_time.elapsed().as_secs_f64();
     let time_remaining = iterations_remaining as f64 / iterations_per_second;
-    let hours = time_remaining as usize / 3600;
-    let minutes = (time_remaining as usize % 3600) / 60;
-    let seconds = time_remaining as usize % 60;
-    print!("\r\x1B[2K");
-    print!("step: {}/{} ", i, config.iterations);
-    print!("time remaining: {}hr {}min {}sec", hours, minutes, seconds);
-    stdout().flush().unwrap();
+    eprint!("\r\x1B[2K");
+    eprint!("step: {}/{} ", i, config.iterations);
+    eprint!("(t = {:.3}) ", simulation.physical_time());
+    eprint!("time remaining: {}", format_time_remaining(time_remaining));
+    Ok(())
+}
+
+/// Times `steps` plain HPP steps from `grid` with `propagate_grid` and again with
+/// `propagate_bitplane`, for `--benchmark` in builds with the `bitplane` feature. Sources and
+/// the rest of `Simulation::advance` are left out, as the bit-planes don't cover them.
+#[cfg(feature = "bitplane")]
+fn benchmark_bitplane(grid: &Grid, steps: usize) {
+    let (width, height) = (grid.width, grid.height);
+    let mut packed = Grid::from_raw(width, height, grid.grid.clone()).expect("a valid grid");
+    let mut next = Grid::new(width, height);
+    let start = Instant::now();
+    for _ in 0..steps {
+        propagate_grid(&packed, &mut next, &TraversalOrder::RowMajor);
+        packed.swap_buffers(&mut next);
+    }
+    let packed_time = start.elapsed();
+    let mut planes = bitplane::BitPlaneGrid::from_grid(grid);
+    let mut next_planes = bitplane::BitPlaneGrid::new(width, height);
+    let start = Instant::now();
+    for _ in 0..steps {
+        bitplane::propagate_bitplane(&planes, &mut next_planes);
+        planes.swap_buffers(&mut next_planes);
+    }
+    let planes_time = start.elapsed();
+    debug_assert_eq!(planes.to_grid().grid, packed.grid);
+    log::info!(
+        "{} plain HPP steps: {:.2?} packed, {:.2?} as bit-planes ({:.1}x)",
+        steps,
+        packed_time,
+        planes_time,
+        packed_time.as_secs_f64() / planes_time.as_secs_f64()
+    );
+}
+
+/// `seconds` as whole hours, minutes and seconds, for time estimates.
+fn format_time_remaining(seconds: f64) -> String {
+    let seconds = seconds as usize;
+    format!(
+        "{}hr {}min {}sec",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
 }
 
+/// Steps timed by `--dry-run` to estimate the run's throughput.
+const DRY_RUN_STEPS: usize = 5;
+
+/// Logs what a run would do, with its remaining time estimated from a few timed steps of
+/// `simulation`, which is left part-way through; see `--dry-run`. Frames, checkpoints and
+/// the other outputs are not timed, so the estimate is a lower bound.
+fn dry_run(mut simulation: Simulation) {
+    let config = &simulation.config;
+    log::info!(
+        "grid {}x{} ({} cells, {} particles), rendered at {}x{} pixels per frame",
+        config.width,
+        config.height,
+        config.width * config.height,
+        simulation.grid.total_particles(),
+        config.width / config.downscale * config.output_scale,
+        config.height / config.downscale * config.output_scale
+    );
+    log::info!(
+        "steps {} to {}, the first {} as warm-up",
+        simulation.step,
+        config.iterations,
+        config.warmup_steps
+    );
+    match config
+        .frameskip
+        .frame_count(config.iterations, config.warmup_steps)
+    {
+        Some(frames) => log::info!("{} frames", frames),
+        None => log::info!("frames every {:?}", config.frameskip),
+    }
+    log::info!(
+        "outputs {:?} written to {}/",
+        config.outputs,
+        config.output_dir
+    );
+    log::info!(
+        "roughly {:.2} GiB of memory",
+        config.estimated_memory_bytes() as f64 / (1u64 << 30) as f64
+    );
+    let remaining = config.iterations.saturating_sub(simulation.step);
+    let steps = DRY_RUN_STEPS.min(remaining);
+    if steps == 0 {
+        log::info!("no steps left to run");
+        return;
+    }
+    let start = Instant::now();
+    simulation.step_many(steps);
+    let steps_per_second = steps as f64 / start.elapsed().as_secs_f64();
+    log::info!(
+        "{:.2} steps per second over {} timed steps: about {} to finish",
+        steps_per_second,
+        steps,
+        format_time_remaining(remaining as f64 / steps_per_second)
+    );
+}
+
+/// Saves the next frame with `save_frame`.
+fn write_frame(simulation: &mut Simulation, sinks: &mut [Box<dyn OutputSink>]) -> io::Result<()> {
+    simulation.frame += 1;
+    save_frame(simulation, sinks)
+}
+
+/// Saves the grid as frame `simulation.frame` to every sink, records it in the frame log and
+/// runs the frame callback; frame 0 is written this way, and every later frame through
+/// `write_frame`.
+fn save_frame(simulation: &mut Simulation, sinks: &mut [Box<dyn OutputSink>]) -> io::Result<()> {
+    for sink in sinks.iter_mut() {
+        sink.write_frame(&simulation.grid, &simulation.config, simulation.frame)?;
+    }
+    simulation.record_frame();
+    if let Some(callback) = &mut simulation.frame_callback {
+        callback(&simulation.grid, simulation.step);
+    }
+    Ok(())
+}
+
+/// Writes the current frame, unless this step already wrote one, and a checkpoint, for a run
+/// stopped before `iterations`. The run can be continued with `--resume output/checkpoint.bin`.
+fn save_final_state(
+    simulation: &mut Simulation,
+    sinks: &mut [Box<dyn OutputSink>],
+    clock: &RunClock,
+) -> io::Result<()> {
+    if clock.last_frame_step != simulation.step {
+        write_frame(simulation, sinks)?;
+    }
+    simulation.save_checkpoint(&simulation.config.output_path("checkpoint.bin"))
+}
+
+/// Estimated memory use above which a run starts with a warning; see
+/// `Config::estimated_memory_bytes`.
+const MEMORY_WARNING_BYTES: u64 = 4 << 30;
+
+/// The binary's `log` backend: every enabled message goes to stderr, clearing the progress
+/// line first. The level comes from `LGCA_LOG` (`error` to `trace`, default `info`).
+/// Applications embedding the engine install their own logger instead.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("\r\x1B[2K[{}] {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
 fn main() {
-    let config = lgca::Config::new(8192, 8192, 16, 20_000, 20, Colouring::VelocityColour);
-    let mut grid_a = Grid::new(config.width, config.height);
-    let mut grid_b = Grid::new(config.width, config.height);
-    grid_a.fill_region(0, 0, config.width, config.height - 1, 0.25);
-    grid_a.set_boundary_at_edge(&config);
-    grid_a.fill_region(3072, 3072, 2048, 2048, 1.0);
-    let mut sources = Vec::<lgca::Source>::new();
-    // sources.push(lgca::Source::new(100, 100, 500, 500, 0.75));
-    // sources.push(Source::new(3500, 3500, 500, 500, 0.00));
-
-    save_grid_as_image(&grid_a, &config, "output/image0.png".into());
-    let start_time = Instant::now();
-    for i in 1..=config.iterations {
-        tick(&config, &mut grid_a, &mut grid_b, &sources, start_time, i);
+    let level = std::env::var("LGCA_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    log::set_logger(&LOGGER).expect("no other logger is installed");
+    log::set_max_level(level);
+
+    let mut config = lgca::Config::new(8192, 8192, 16, 20_000, 20, Colouring::VelocityColour);
+    config.show_progress = true;
+    let args: Vec<String> = std::env::args().collect();
+    let flag_value = |flag: &str| {
+        args.iter().position(|arg| arg == flag).map(|i| {
+            args.get(i + 1)
+                .unwrap_or_else(|| {
+                    log::error!("{} requires a value", flag);
+                    std::process::exit(1);
+                })
+                .as_str()
+        })
+    };
+    let resume_from = flag_value("--resume");
+    if let Some(frame_dir) = flag_value("--assemble") {
+        // Re-encode an existing frame directory as a gif, without simulating.
+        let output = Path::new(frame_dir).join("output.gif");
+        let output = output.to_str().expect("frame directory is valid UTF-8");
+        match output::assemble_video(frame_dir, "image{}.png", output, output::GIF_FRAME_DELAY) {
+            Ok(frames) => log::info!("wrote {} frames to {}", frames, output),
+            Err(error) => {
+                log::error!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(size) = flag_value("--benchmark") {
+        // Time the standard benchmark scenario, without writing any output.
+        let size = size.parse().unwrap_or_else(|_| {
+            log::error!("--benchmark needs a grid size, got {}", size);
+            std::process::exit(1);
+        });
+        if size < lgca::BENCHMARK_MIN_SIZE {
+            log::error!(
+                "--benchmark grids are at least {}, got {}",
+                lgca::BENCHMARK_MIN_SIZE,
+                size
+            );
+            std::process::exit(1);
+        }
+        let (grid, config, sources) = lgca::benchmark_scenario(size);
+        let steps = config.iterations;
+        #[cfg(feature = "bitplane")]
+        benchmark_bitplane(&grid, steps);
+        let mut simulation = Simulation::new(config, grid, sources);
+        let start = Instant::now();
+        simulation.step_many(steps);
+        let elapsed = start.elapsed();
+        log::info!(
+            "{} steps of the {}x{} benchmark in {:.2?}: {:.1} million cell updates per second",
+            steps,
+            size,
+            size,
+            elapsed,
+            (steps * size * size) as f64 / elapsed.as_secs_f64() / 1e6
+        );
+        return;
+    }
+
+    if let Some(mode) = flag_value("--summary-image") {
+        // One image combining every frame, written as summary.png at the end of the run.
+        let mode = match mode {
+            "max" => SummaryMode::Max,
+            "sum" => SummaryMode::Sum,
+            _ => {
+                log::error!("--summary-image needs max or sum, got {}", mode);
+                std::process::exit(1);
+            }
+        };
+        config.outputs.push(OutputKind::Summary(mode));
+    }
+
+    let mut grid = Grid::new(config.width, config.height);
+    let sources = match flag_value("--scene") {
+        Some(filename) => {
+            let scene = scene::Scene::load(filename).unwrap_or_else(|error| {
+                log::error!("{}: {}", filename, error);
+                std::process::exit(1);
+            });
+            scene.apply(&mut grid, &config).unwrap_or_else(|error| {
+                log::error!("{}: {}", filename, error);
+                std::process::exit(1);
+            })
+        }
+        None => {
+            grid.fill_region(0, 0, config.width, config.height - 1, 0.25);
+            grid.set_boundary_at_edge(&config, 1);
+            grid.fill_region(3072, 3072, 2048, 2048, 1.0);
+            let sources = Vec::<lgca::Source>::new();
+            // sources.push(lgca::Source::new(100, 100, 500, 500, 0.75));
+            // sources.push(Source::new(3500, 3500, 500, 500, 0.00));
+            sources
+        }
+    };
+
+    if let Err(errors) = config.validate() {
+        for error in errors {
+            log::error!("invalid config: {}", error);
+        }
+        std::process::exit(1);
+    }
+    if let Some(filename) = flag_value("--boundary-map") {
+        // Check the obstacle geometry without simulating.
+        match render::save_boundary_map(&grid, &config, filename) {
+            Ok(()) => log::info!("wrote the boundary map to {}", filename),
+            Err(error) => {
+                log::error!("{}: {}", filename, error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let memory = config.estimated_memory_bytes();
+    if memory > MEMORY_WARNING_BYTES {
+        log::warn!(
+            "this run needs roughly {:.1} GiB of memory",
+            memory as f64 / (1u64 << 30) as f64
+        );
+    }
+    let mut simulation = match resume_from {
+        Some(filename) => {
+            let checkpoint = checkpoint::Checkpoint::load(filename).unwrap_or_else(|error| {
+                log::error!("failed to read {}: {}", filename, error);
+                std::process::exit(1);
+            });
+            Simulation::resume(config, checkpoint, sources).unwrap_or_else(|error| {
+                log::error!("can't resume from {}: {}", filename, error);
+                std::process::exit(1);
+            })
+        }
+        None => Simulation::new(config, grid, sources),
+    };
+    // Checked against the grid that will run, which after a resume is the checkpoint's.
+    if let Err(error) = lgca::validate_sources(&simulation.grid, &simulation.sources) {
+        log::error!("{}", error);
+        std::process::exit(1);
+    }
+    if args.iter().any(|arg| arg == "--dry-run") {
+        // Check the setup and estimate the run time, without writing anything.
+        dry_run(simulation);
+        return;
+    }
+    // The first Ctrl-C stops after the current step with a checkpoint; a second one exits
+    // immediately.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || {
+        if handler_flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    })
+    .expect("Failed to install the Ctrl-C handler");
+    simulation.interrupt = Some(interrupted);
+
+    let checkpoint_path = simulation.config.output_path("checkpoint.bin");
+    let (_, stats) = simulation.run_to_completion().unwrap_or_else(|error| {
+        log::error!("{}", error);
+        std::process::exit(1);
+    });
+    if let Some(reason) = stats.stopped_early {
+        log::warn!("{}, checkpoint saved to {}", reason, checkpoint_path);
+    }
+    if let Some(step) = stats.steady_state_step {
+        log::info!("steady state reached at step {}", step);
+    }
+    log::info!(
+        "finished at step {} after {:.1?}, frames 0 to {} written",
+        stats.steps,
+        stats.elapsed,
+        stats.last_frame
+    );
+    log::info!(
+        "occupancy (cells by particle count 0-4): {:?}",
+        stats.occupancy
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// The width, height and cells of grids of up to 12x12 cells, each any combination of
+    /// particles, marked as boundary with probability `wall_probability`.
+    fn arbitrary_grid(wall_probability: f64) -> impl Strategy<Value = (usize, usize, Vec<Cell>)> {
+        (1_usize..=12, 1_usize..=12).prop_flat_map(move |(width, height)| {
+            let value = (
+                0..=lgca::cell::FULL,
+                proptest::bool::weighted(wall_probability),
+            )
+                .prop_map(|(particles, wall)| {
+                    if wall {
+                        particles | lgca::cell::BOUNDARY
+                    } else {
+                        particles
+                    }
+                });
+            (
+                Just(width),
+                Just(height),
+                proptest::collection::vec(value, width * height),
+            )
+        })
+    }
+
+    /// One periodic step of the grid with the given cells.
+    fn step_periodic(width: usize, height: usize, cells: Vec<Cell>) -> (Grid, Grid) {
+        let grid = Grid::from_raw(width, height, cells).unwrap();
+        let mut next_grid = Grid::new(width, height);
+        propagate_periodic(&grid, &mut next_grid);
+        (grid, next_grid)
+    }
+
+    proptest! {
+        #[test]
+        fn periodic_steps_conserve_mass_and_momentum(
+            (width, height, cells) in arbitrary_grid(0.0)
+        ) {
+            let (grid, next_grid) = step_periodic(width, height, cells);
+            prop_assert_eq!(next_grid.total_particles(), grid.total_particles());
+            prop_assert_eq!(total_momentum(&next_grid), total_momentum(&grid));
+        }
+
+        #[test]
+        fn steps_with_walls_conserve_mass((width, height, cells) in arbitrary_grid(0.2)) {
+            let (grid, next_grid) = step_periodic(width, height, cells);
+            prop_assert_eq!(next_grid.total_particles(), grid.total_particles());
+        }
+    }
+
+    #[test]
+    fn randomizing_collisions_keep_mass_and_relax_momentum() {
+        let mut config = Config::new(32, 32, 1, 1, 1, Colouring::DensityBW);
+        config.boundary_mode = BoundaryMode::Periodic;
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_pattern(Region::new(0, 0, 32, 32), 1.0, lgca::cell::RIGHT);
+        let copy = Grid::from_raw(32, 32, grid.grid.clone()).unwrap();
+        let mut hpp = Simulation::new(config.clone(), copy, Vec::new());
+        config.collision_mode = CollisionMode::HppPlusRandomization { rate: 1.0 };
+        let mut randomized = Simulation::new(config, grid, Vec::new());
+        hpp.step_many(3);
+        randomized.step_many(3);
+        assert_eq!(total_momentum(&hpp.grid), (1024, 0));
+        assert_eq!(randomized.grid.total_particles(), 1024);
+        assert!(total_momentum(&randomized.grid).0 < 256);
+    }
+
+    #[test]
+    fn mirror_edges_step_as_half_of_a_symmetric_grid() {
+        // A periodic grid that is its own mirror image about x = 8 (and so about x = 0 too)
+        // must keep its left half exactly as a half-width grid with mirror side edges does.
+        let (width, height) = (16, 12);
+        let mut half = Grid::new(width / 2, height);
+        half.fill(Region::new(0, 0, width / 2, height), 0.4);
+        half.set_boundary(Region::new(3, 4, 2, 3));
+        let mut full = Grid::new(width, height);
+        for (x, y) in Region::new(0, 0, width / 2, height).cells() {
+            let value = half.get(x, y);
+            full.set(x, y, value);
+            let mirrored_x = width as isize - 1 - x;
+            full.set(mirrored_x, y, lgca::cell::mirror_horizontal(value));
+        }
+        let edges = EdgeConditions {
+            left: EdgeCondition::Mirror,
+            right: EdgeCondition::Mirror,
+            ..EdgeConditions::uniform(EdgeCondition::Periodic)
+        };
+        let particles = half.total_particles();
+        let mut next_half = Grid::new(half.width, half.height);
+        let mut next_full = Grid::new(width, height);
+        for _ in 0..20 {
+            propagate_edges(&half, &mut next_half, &edges);
+            propagate_periodic(&full, &mut next_full);
+            half.swap_buffers(&mut next_half);
+            full.swap_buffers(&mut next_full);
+        }
+        for (x, y) in Region::new(0, 0, width / 2, height).cells() {
+            assert_eq!(half.get(x, y), full.get(x, y), "({}, {})", x, y);
+        }
+        assert_eq!(half.total_particles(), particles);
+    }
+
+    #[test]
+    fn closed_edges_conserve_mass_without_edge_walls() {
+        let config = Config::new(8, 8, 1, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill(Region::new(0, 0, 8, 8), 0.4);
+        grid.set(7, 3, lgca::cell::RIGHT);
+        let particles = grid.total_particles();
+        let mut next_grid = Grid::new(config.width, config.height);
+        for _ in 0..50 {
+            propagate_serial(&grid, &mut next_grid, &config);
+            grid.swap_buffers(&mut next_grid);
+            assert_eq!(grid.total_particles(), particles);
+        }
+    }
+
+    #[test]
+    fn wall_edges_conserve_mass_without_edge_walls() {
+        let mut config = Config::new(8, 8, 1, 1, 1, Colouring::DensityBW);
+        config.boundary_mode = BoundaryMode::Edges(EdgeConditions::uniform(EdgeCondition::Wall));
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill(Region::new(0, 0, 8, 8), 0.4);
+        grid.set(7, 3, lgca::cell::RIGHT);
+        let particles = grid.total_particles();
+        let mut next_grid = Grid::new(config.width, config.height);
+        for _ in 0..50 {
+            propagate_serial(&grid, &mut next_grid, &config);
+            grid.swap_buffers(&mut next_grid);
+            assert_eq!(grid.total_particles(), particles);
+        }
+    }
+
+    #[test]
+    fn in_place_steps_match_the_double_buffer() {
+        let mirrored = EdgeConditions {
+            top: EdgeCondition::Mirror,
+            bottom: EdgeCondition::Open,
+            ..EdgeConditions::uniform(EdgeCondition::Periodic)
+        };
+        let modes = [
+            BoundaryMode::Closed,
+            BoundaryMode::Periodic,
+            BoundaryMode::Edges(mirrored),
+        ];
+        // Down to one and two rows, where the held-back rows overlap.
+        for (mode, (width, height)) in modes
+            .into_iter()
+            .flat_map(|mode| [(24, 17), (5, 2), (7, 1)].map(|size| (mode, size)))
+        {
+            let mut config = Config::new(width, height, 1, 1, 1, Colouring::DensityBW);
+            config.boundary_mode = mode;
+            let mut grid = Grid::new(width, height);
+            grid.fill(Region::new(0, 0, width, height), 0.4);
+            grid.set_boundary(Region::new(2, 0, 2, 1));
+            let copy = Grid::from_raw(width, height, grid.grid.clone()).unwrap();
+            let double_buffered =
+                |grid: &Grid, next: &mut Grid| propagate_serial(grid, next, &config);
+            let in_place = |grid: &Grid, next: &mut Grid| {
+                next.grid.copy_from_slice(&grid.grid);
+                propagate_inplace(next, &config);
+            };
+            let divergence = find_first_divergence(grid, copy, 30, double_buffered, in_place);
+            assert!(divergence.is_none(), "{:?} {:?}", mode, divergence);
+        }
+    }
+
+    #[test]
+    fn run_to_completion_writes_the_configured_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::new(32, 16, 4, 10, 5, Colouring::DensityBW);
+        config.output_dir = dir.path().join("run").to_str().unwrap().to_string();
+        config.log_frames = true;
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.3);
+        grid.set_boundary_at_edge(&config, 1);
+        let particles = grid.total_particles();
+        let mut simulation = Simulation::new(config.clone(), grid, Vec::new());
+        let frame_steps = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let callback_steps = frame_steps.clone();
+        simulation.frame_callback = Some(Box::new(move |_, step| {
+            callback_steps.borrow_mut().push(step);
+        }));
+        let (grid, stats) = simulation.run_to_completion().unwrap();
+        // Frames 0 to 2, the initial state included.
+        assert_eq!(*frame_steps.borrow(), [0, 5, 10]);
+        assert_eq!((stats.steps, stats.last_frame), (10, 2));
+        assert!(stats.stopped_early.is_none());
+        assert_eq!(grid.total_particles(), particles);
+        for file in ["image0.png", "image1.png", "image2.png", "frames.csv"] {
+            assert!(Path::new(&config.output_path(file)).exists(), "{}", file);
+        }
+        let frame_log = std::fs::read_to_string(config.output_path("frames.csv")).unwrap();
+        let frames: Vec<&str> = frame_log
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').next().unwrap())
+            .collect();
+        assert_eq!(frames, ["0", "1", "2"]);
+
+        // Diagnostics every other step while frames stay every five.
+        config.diagnostics_stride = Some(2);
+        let simulation = Simulation::new(config.clone(), Grid::new(32, 16), Vec::new());
+        let (_, stats) = simulation.run_to_completion().unwrap();
+        assert_eq!(stats.last_frame, 2);
+        let frame_log = std::fs::read_to_string(config.output_path("frames.csv")).unwrap();
+        let steps: Vec<&str> = frame_log
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').nth(1).unwrap())
+            .collect();
+        assert_eq!(steps, ["2", "4", "6", "8", "10"]);
+
+        // A directory in the way of a frame fails the run instead of panicking.
+        std::fs::remove_file(config.output_path("image1.png")).unwrap();
+        std::fs::create_dir(config.output_path("image1.png")).unwrap();
+        let simulation = Simulation::new(config.clone(), Grid::new(32, 16), Vec::new());
+        assert!(matches!(
+            simulation.run_to_completion(),
+            Err(simulation::RunError::Io(_))
+        ));
+
+        config.downscale = 0;
+        let simulation = Simulation::new(config, Grid::new(32, 16), Vec::new());
+        assert!(matches!(
+            simulation.run_to_completion(),
+            Err(simulation::RunError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn resume_rejects_a_checkpoint_of_another_size() {
+        let config = Config::new(32, 16, 4, 10, 5, Colouring::DensityBW);
+        let checkpoint = |width, height| checkpoint::Checkpoint {
+            step: 6,
+            frame: 2,
+            grid: Grid::new(width, height),
+        };
+        let simulation = Simulation::resume(config.clone(), checkpoint(32, 16), Vec::new());
+        let simulation = simulation.unwrap();
+        assert_eq!((simulation.step, simulation.frame), (6, 2));
+        let error = Simulation::resume(config, checkpoint(16, 32), Vec::new()).err();
+        assert_eq!(error.unwrap().checkpoint, (16, 32));
+    }
+
+    #[test]
+    fn seeded_runs_repeat_exactly() {
+        let run = |seed: u64| {
+            let (grid, mut config, mut sources) = lgca::benchmark_scenario(32);
+            config.seed = Some(seed);
+            config.sponges.push(Sponge::new(24, 0, 8, 32, 0.5));
+            config.collision_mode = CollisionMode::HppPlusRandomization { rate: 0.1 };
+            sources.push(Source::additive(Region::new(20, 4, 4, 4), 0.7));
+            sources.push(Source::profiled(
+                Region::new(8, 20, 2, 6),
+                0.4,
+                (0.3, 0.0),
+                Profile::Parabolic,
+            ));
+            let mut simulation = Simulation::new(config, grid, sources);
+            let trajectory = Box::new(|step: usize| (12 + step as isize / 4, 12));
+            let piston = obstacle::MovingObstacle::rectangle(2, 4, trajectory, 0.3);
+            simulation.obstacles.push(piston);
+            simulation.step_many(20);
+            simulation.grid.grid
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn traversal_orders_produce_identical_grids() {
+        let config = Config::new(96, 64, 4, 10, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.4);
+        grid.set_boundary_at_edge(&config, 1);
+        grid.fill_boundary(40, 20, 8, 8);
+        let mut row_major = Grid::new(config.width, config.height);
+        let mut column_major = Grid::new(config.width, config.height);
+        propagate_grid(&grid, &mut row_major, &TraversalOrder::RowMajor);
+        propagate_grid(&grid, &mut column_major, &TraversalOrder::ColumnMajor);
+        assert_eq!(row_major.grid, column_major.grid);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_execution_matches_serial() {
+        let (grid, mut config, _) = lgca::benchmark_scenario(64);
+        config.downscale = 4;
+        let mut serial = Grid::new(config.width, config.height);
+        let mut parallel = Grid::new(config.width, config.height);
+        propagate_grid(&grid, &mut serial, &TraversalOrder::RowMajor);
+        par_propagate_grid(&grid, &mut parallel, next_cell);
+        assert_eq!(serial.grid, parallel.grid);
+        propagate_periodic(&grid, &mut serial);
+        par_propagate_grid(&grid, &mut parallel, next_cell_periodic);
+        assert_eq!(serial.grid, parallel.grid);
+
+        config.execution_mode = lgca::ExecutionMode::Serial;
+        let serial_frame = render::Renderer::new(&config).render(&grid).to_vec();
+        config.execution_mode = lgca::ExecutionMode::Parallel;
+        let mut renderer = render::Renderer::new(&config);
+        assert_eq!(renderer.render(&grid), serial_frame);
+        let mut streamed = Vec::new();
+        renderer.write(&grid, &mut streamed).unwrap();
+        assert_eq!(streamed, serial_frame);
+    }
+
+    #[test]
+    fn injected_particles_account_for_the_change_in_mass() {
+        let config = Config::new(48, 32, 4, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.25);
+        grid.set_boundary_at_edge(&config, 1);
+        let initial = grid.total_particles() as i64;
+        let sources = vec![
+            Source::new(4, 4, 8, 8, 0.75),
+            Source::new(30, 10, 6, 6, 0.0),
+        ];
+        let mut simulation = Simulation::new(config, grid, sources);
+        simulation.step_many(20);
+        assert_ne!(simulation.injected_particles, 0);
+        assert_eq!(
+            simulation.grid.total_particles() as i64,
+            initial + simulation.injected_particles
+        );
+    }
+
+    #[test]
+    fn ticks_write_decodable_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::new(64, 32, 4, 6, 2, Colouring::VelocityColour);
+        config.output_dir = dir.path().to_str().unwrap().to_string();
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.25);
+        grid.set_boundary_at_edge(&config, 1);
+        let sources = vec![Source::new(4, 4, 8, 8, 0.75)];
+        let mut sinks = output::build_sinks(&config);
+        let mut simulation = Simulation::new(config, grid, sources);
+        let frame_steps = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let callback_steps = frame_steps.clone();
+        simulation.frame_callback = Some(Box::new(move |_, step| {
+            callback_steps.borrow_mut().push(step);
+        }));
+        let mut clock = RunClock::new(0);
+        for _ in 0..simulation.config.iterations {
+            tick(&mut simulation, &mut sinks, &mut clock).unwrap();
+        }
+        assert_eq!(*frame_steps.borrow(), [2, 4, 6]);
+        for sink in sinks.iter_mut() {
+            sink.finish().unwrap();
+        }
+        let images: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+            .collect();
+        assert_eq!(images.len(), 3);
+        for path in images {
+            let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+            let mut reader = decoder.read_info().unwrap();
+            let mut pixels = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut pixels).unwrap();
+            assert_eq!((info.width, info.height), (16, 8));
+        }
+    }
+
+    #[test]
+    fn stream_moves_particles_without_colliding() {
+        let mut grid = Grid::new(8, 8);
+        grid.set(3, 3, lgca::cell::UP | lgca::cell::DOWN);
+        grid.set(5, 5, lgca::cell::RIGHT);
+        let mut next = Grid::new(8, 8);
+        stream(&grid, &mut next);
+        assert_eq!(next.get(3, 4), lgca::cell::UP);
+        assert_eq!(next.get(3, 2), lgca::cell::DOWN);
+        assert_eq!(next.get(6, 5), lgca::cell::RIGHT);
+        assert_eq!(next.grid.iter().filter(|&&value| value != 0).count(), 3);
+
+        // Four particles converge on the centre (4, 4) without scattering.
+        grid.fill_test_pattern(TestPattern::Converging);
+        stream(&grid, &mut next);
+        assert_eq!(next.get(4, 4), lgca::cell::FULL);
+        assert_eq!(next.total_particles(), 4);
+    }
+
+    #[test]
+    fn test_patterns_stream_and_collide_as_documented() {
+        let mut grid = Grid::new(9, 7);
+        let mut next = Grid::new(9, 7);
+        grid.fill_test_pattern(TestPattern::CentreParticle);
+        propagate_grid(&grid, &mut next, &TraversalOrder::RowMajor);
+        assert_eq!(next.get(5, 3), lgca::cell::RIGHT);
+        assert_eq!(next.total_particles(), 1);
+
+        grid.fill_test_pattern(TestPattern::HeadOnPair);
+        propagate_grid(&grid, &mut next, &TraversalOrder::RowMajor);
+        assert_eq!(next.get(4, 3), lgca::cell::UP | lgca::cell::DOWN);
+        assert_eq!(next.total_particles(), 2);
+
+        // Away from the open edges, every cell swaps direction.
+        grid.fill_test_pattern(TestPattern::Checkerboard);
+        propagate_grid(&grid, &mut next, &TraversalOrder::RowMajor);
+        for (x, y) in Region::new(0, 1, 9, 5).cells() {
+            assert_eq!(
+                next.get(x, y),
+                grid.get(x, y) ^ (lgca::cell::UP | lgca::cell::DOWN)
+            );
+        }
+    }
+
+    #[test]
+    fn collide_scatters_pairs_in_place() {
+        use lgca::cell::{BOUNDARY, DOWN, LEFT, RIGHT, UP};
+        let config = Config::new(8, 8, 1, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.set_boundary_at_edge(&config, 1);
+        grid.set(3, 3, UP | DOWN);
+        grid.set(4, 4, UP | RIGHT);
+        grid.set(3, 7, UP | BOUNDARY);
+        collide(&mut grid);
+        assert_eq!(grid.get(3, 3), RIGHT | LEFT);
+        assert_eq!(grid.get(4, 4), UP | RIGHT);
+        assert_eq!(grid.get(3, 7), DOWN | BOUNDARY);
+    }
+
+    #[test]
+    fn stream_then_collide_matches_propagate_grid() {
+        let config = Config::new(96, 64, 4, 10, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.4);
+        grid.set_boundary_at_edge(&config, 1);
+        grid.fill_boundary(40, 20, 8, 8);
+        let mut fused = Grid::new(config.width, config.height);
+        let mut split = Grid::new(config.width, config.height);
+        propagate_grid(&grid, &mut fused, &TraversalOrder::RowMajor);
+        stream(&grid, &mut split);
+        collide(&mut split);
+        assert_eq!(split.grid, fused.grid);
+    }
+
+    #[test]
+    fn only_head_on_pairs_collide() {
+        for value in 0..=lgca::cell::FULL {
+            let expected = match value {
+                0b0101 => 0b1010,
+                0b1010 => 0b0101,
+                other => other,
+            };
+            assert_eq!(resolve_collisions(value), expected, "cell {:#06b}", value);
+        }
+    }
+
+    #[test]
+    fn boundary_reverses_single_particles() {
+        use lgca::cell::{BOUNDARY, DOWN, LEFT, RIGHT, UP};
+        for (incoming, outgoing) in [(UP, DOWN), (RIGHT, LEFT), (DOWN, UP), (LEFT, RIGHT)] {
+            assert_eq!(resolve_collisions(incoming | BOUNDARY), outgoing | BOUNDARY);
+        }
+        assert_eq!(resolve_collisions(BOUNDARY), BOUNDARY);
+    }
+
+    #[test]
+    fn top_wall_reflects_only_the_normal_component() {
+        use lgca::cell::{DOWN, LEFT, RIGHT, UP};
+        // Fluid lies below a top wall.
+        assert_eq!(reflect_at_wall(UP, DOWN), DOWN);
+        assert_eq!(reflect_at_wall(LEFT, DOWN), LEFT);
+        assert_eq!(reflect_at_wall(UP | RIGHT, DOWN), DOWN | RIGHT);
+    }
+
+    #[test]
+    fn particles_inside_a_wall_slide_along_it() {
+        // Bounce-back would send the particle in the top wall back to the right; with fluid
+        // below, it keeps going left along the wall instead.
+        let config = Config::new(5, 3, 1, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.set_boundary(Region::new(0, 2, 5, 1));
+        grid.set(3, 2, lgca::cell::BOUNDARY | lgca::cell::LEFT);
+        let mut next_grid = Grid::new(config.width, config.height);
+        for x in [2, 1, 0] {
+            propagate_grid(&grid, &mut next_grid, &config.traversal_order);
+            grid.swap_buffers(&mut next_grid);
+            assert_eq!(grid.get(x, 2), lgca::cell::BOUNDARY | lgca::cell::LEFT);
+        }
+    }
+
+    #[test]
+    fn corners_reverse_every_particle() {
+        use lgca::cell::{DOWN, FULL, LEFT, RIGHT, UP};
+        // Convex corner with fluid above and to the left.
+        assert_eq!(reflect_at_wall(DOWN | RIGHT, UP | LEFT), UP | LEFT);
+        // Concave corner: no fluid neighbours.
+        assert_eq!(reflect_at_wall(UP | LEFT, lgca::cell::EMPTY), DOWN | RIGHT);
+        assert_eq!(reflect_at_wall(FULL, UP | LEFT), FULL);
+    }
+
+    #[test]
+    fn particle_bounces_off_top_wall() {
+        let config = Config::new(8, 8, 1, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.set_boundary_at_edge(&config, 1);
+        grid.set(3, 6, lgca::cell::UP);
+        let mut next = Grid::new(config.width, config.height);
+        propagate_grid(&grid, &mut next, &TraversalOrder::RowMajor);
+        assert_eq!(next.get(3, 7), lgca::cell::DOWN | lgca::cell::BOUNDARY);
+        propagate_grid(&next, &mut grid, &TraversalOrder::RowMajor);
+        assert_eq!(grid.get(3, 6), lgca::cell::DOWN);
     }
 }
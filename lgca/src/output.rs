@@ -0,0 +1,530 @@
+#![allow(dead_code)]
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, stdout, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    lgca::{Block, Config, Grid, OutputKind, SummaryMode},
+    render::Renderer,
+    spectrum::{energy_spectrum, save_energy_spectrum, VelocityField},
+};
+
+/// Receives each saved frame of a run. `tick` fans every frame out to all active sinks, and
+/// the first error ends the run.
+pub trait OutputSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, frame: usize) -> io::Result<()>;
+
+    /// Called once after the last frame, for sinks that hold an open file.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Prefixes an error with the file it came from, since a sink's error reaches the caller with
+/// no other record of which output failed.
+pub(crate) fn in_file(filename: &str) -> impl FnOnce(io::Error) -> io::Error + '_ {
+    move |error| io::Error::new(error.kind(), format!("{}: {}", filename, error))
+}
+
+/// Builds one sink per entry in `config.outputs`.
+pub fn build_sinks(config: &Config) -> Vec<Box<dyn OutputSink>> {
+    config
+        .outputs
+        .iter()
+        .map(|kind| -> Box<dyn OutputSink> {
+            match kind {
+                OutputKind::Image => Box::new(ImageSink::new(config)),
+                OutputKind::Csv => Box::new(CsvSink),
+                OutputKind::Raw => Box::new(RawSink),
+                OutputKind::Gif => {
+                    Box::new(GifSink::new(&config.output_path("output.gif"), config))
+                }
+                OutputKind::RawVideo => Box::new(StdoutSink::new(config)),
+                OutputKind::Spectrum => Box::new(SpectrumSink),
+                OutputKind::VelocityStats => Box::new(VelocityStatsSink::new(
+                    &config.output_path("velocity_stats.csv"),
+                )),
+                OutputKind::Summary(mode) => Box::new(SummarySink::new(
+                    &config.output_path("summary.png"),
+                    config,
+                    *mode,
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Writes `image{frame}.{png,bmp,ppm}` using the configured colouring and format.
+pub struct ImageSink {
+    renderer: Renderer,
+}
+
+impl ImageSink {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            renderer: Renderer::new(config),
+        }
+    }
+}
+
+impl OutputSink for ImageSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, frame: usize) -> io::Result<()> {
+        let filename = config.output_path(&format!(
+            "image{}.{}",
+            frame,
+            config.image_format.extension()
+        ));
+        self.renderer
+            .save(grid, &filename)
+            .map_err(in_file(&filename))
+    }
+}
+
+/// Combines every frame into one image, saved when the run finishes; see
+/// `OutputKind::Summary`.
+pub struct SummarySink {
+    filename: String,
+    renderer: Renderer,
+    mode: SummaryMode,
+}
+
+impl SummarySink {
+    pub fn new(filename: &str, config: &Config, mode: SummaryMode) -> Self {
+        Self {
+            filename: filename.to_string(),
+            renderer: Renderer::new(config),
+            mode,
+        }
+    }
+}
+
+impl OutputSink for SummarySink {
+    fn write_frame(&mut self, grid: &Grid, _config: &Config, _frame: usize) -> io::Result<()> {
+        self.renderer.accumulate(grid, self.mode);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.renderer
+            .save_summary(&self.filename)
+            .map_err(in_file(&self.filename))
+    }
+}
+
+/// Writes `velocity{frame}.csv` with the mean velocity of every block.
+pub struct CsvSink;
+
+impl OutputSink for CsvSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, frame: usize) -> io::Result<()> {
+        let filename = config.output_path(&format!("velocity{}.csv", frame));
+        save_block_velocities(&filename, grid, config).map_err(in_file(&filename))
+    }
+}
+
+/// Writes the mean velocity of every `config.downscale` block as `block_x,block_y,vx,vy` rows.
+fn save_block_velocities(filename: &str, grid: &Grid, config: &Config) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(writer, "block_x,block_y,vx,vy")?;
+    for block_y in 0..grid.height / config.downscale {
+        for block_x in 0..grid.width / config.downscale {
+            let block = Block::with_boundary_mode(
+                block_x,
+                block_y,
+                config.downscale,
+                grid,
+                config.boundary_mode,
+            );
+            let (vx, vy) = block.velocity();
+            writeln!(writer, "{},{},{},{}", block_x, block_y, vx, vy)?;
+        }
+    }
+    writer.flush()
+}
+
+/// Writes `spectrum{frame}.csv`, the kinetic-energy spectrum of the block velocities.
+pub struct SpectrumSink;
+
+impl OutputSink for SpectrumSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, frame: usize) -> io::Result<()> {
+        let filename = config.output_path(&format!("spectrum{}.csv", frame));
+        let field = VelocityField::from_grid(grid, config.downscale);
+        save_energy_spectrum(&filename, &energy_spectrum(&field)).map_err(in_file(&filename))
+    }
+}
+
+/// Appends a `frame,mean,variance,max` row of block speed statistics for every frame.
+pub struct VelocityStatsSink {
+    filename: String,
+    writer: Option<BufWriter<File>>,
+}
+
+impl VelocityStatsSink {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            filename: filename.to_string(),
+            writer: None,
+        }
+    }
+}
+
+impl OutputSink for VelocityStatsSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, frame: usize) -> io::Result<()> {
+        let stats = grid.velocity_statistics(config.downscale);
+        let mut write = || {
+            let writer = match &mut self.writer {
+                Some(writer) => writer,
+                None => {
+                    let mut writer = BufWriter::new(File::create(&self.filename)?);
+                    writeln!(writer, "frame,mean,variance,max")?;
+                    self.writer.insert(writer)
+                }
+            };
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                frame, stats.mean, stats.variance, stats.max
+            )
+        };
+        write().map_err(in_file(&self.filename))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.writer.take() {
+            Some(mut writer) => writer.flush().map_err(in_file(&self.filename)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes `grid{frame}.raw`, the cell bytes in `y * width + x` order.
+pub struct RawSink;
+
+impl OutputSink for RawSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, frame: usize) -> io::Result<()> {
+        let filename = config.output_path(&format!("grid{}.raw", frame));
+        fs::write(&filename, &grid.grid).map_err(in_file(&filename))
+    }
+}
+
+/// Writes each frame's raw RGB bytes to stdout; see `OutputKind::RawVideo`.
+pub struct StdoutSink {
+    renderer: Renderer,
+}
+
+impl StdoutSink {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            renderer: Renderer::new(config),
+        }
+    }
+}
+
+impl OutputSink for StdoutSink {
+    fn write_frame(&mut self, grid: &Grid, _config: &Config, _frame: usize) -> io::Result<()> {
+        let mut out = stdout().lock();
+        self.renderer
+            .write(grid, &mut out)
+            .and_then(|_| out.flush())
+            .map_err(in_file("stdout"))
+    }
+}
+
+/// Delay between gif frames written by `GifSink`, in hundredths of a second.
+pub const GIF_FRAME_DELAY: u16 = 3;
+
+/// Appends every frame to a single looping animated gif.
+pub struct GifSink {
+    writer: GifWriter,
+    renderer: Renderer,
+}
+
+impl GifSink {
+    pub fn new(filename: &str, config: &Config) -> Self {
+        Self {
+            writer: GifWriter::new(filename, GIF_FRAME_DELAY),
+            renderer: Renderer::new(config),
+        }
+    }
+}
+
+impl OutputSink for GifSink {
+    fn write_frame(&mut self, grid: &Grid, config: &Config, _frame: usize) -> io::Result<()> {
+        let frame_size = |pixels: usize| {
+            u16::try_from(pixels).expect("gif frames are at most 65535 pixels a side")
+        };
+        let width = frame_size(grid.width / config.downscale);
+        let height = frame_size(grid.height / config.downscale);
+        let image_data = self.renderer.render(grid);
+        self.writer
+            .write_rgb(width, height, image_data)
+            .map_err(in_file(&self.writer.filename))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.finish().map_err(in_file(&self.writer.filename))
+    }
+}
+
+/// The encoding half of `GifSink`: appends RGB frames to a looping gif, creating the file on
+/// the first frame.
+struct GifWriter {
+    filename: String,
+    /// Delay between frames, in hundredths of a second.
+    delay: u16,
+    encoder: Option<gif::Encoder<BufWriter<File>>>,
+}
+
+impl GifWriter {
+    fn new(filename: &str, delay: u16) -> Self {
+        Self {
+            filename: filename.to_string(),
+            delay,
+            encoder: None,
+        }
+    }
+
+    fn write_rgb(&mut self, width: u16, height: u16, image_data: &[u8]) -> io::Result<()> {
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            None => {
+                let file = File::create(&self.filename)?;
+                let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &[])
+                    .map_err(io::Error::other)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+                self.encoder.insert(encoder)
+            }
+        };
+        let mut gif_frame = gif::Frame::from_rgb_speed(width, height, image_data, 10);
+        gif_frame.delay = self.delay;
+        encoder.write_frame(&gif_frame).map_err(io::Error::other)
+    }
+
+    /// Writes the gif trailer and flushes the file.
+    fn finish(&mut self) -> io::Result<()> {
+        match self.encoder.take() {
+            Some(encoder) => encoder.into_inner()?.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Why `assemble_video` could not build a gif.
+#[derive(Debug)]
+pub enum AssembleError {
+    /// The pattern has no `{}` for the frame number.
+    InvalidPattern(String),
+    /// No file in the directory matches the pattern.
+    NoFrames {
+        dir: String,
+        pattern: String,
+    },
+    /// A frame is a different size from the first one.
+    SizeMismatch {
+        file: PathBuf,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    /// A frame is not 8-bit RGB or RGBA, or is more than 65535 pixels a side.
+    Unsupported(PathBuf),
+    Io(io::Error),
+    Decode(PathBuf, png::DecodingError),
+    /// The gif could not be created or written.
+    Write(String, io::Error),
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleError::InvalidPattern(pattern) => write!(
+                f,
+                "frame pattern \"{}\" has no {{}} for the frame number",
+                pattern
+            ),
+            AssembleError::NoFrames { dir, pattern } => {
+                write!(f, "no frames matching \"{}\" in {}", pattern, dir)
+            }
+            AssembleError::SizeMismatch {
+                file,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} is {}x{}, but the first frame is {}x{}",
+                file.display(),
+                found.0,
+                found.1,
+                expected.0,
+                expected.1
+            ),
+            AssembleError::Unsupported(file) => write!(
+                f,
+                "{} is not an 8-bit RGB or RGBA image that fits in a gif",
+                file.display()
+            ),
+            AssembleError::Io(error) => write!(f, "failed to read frames: {}", error),
+            AssembleError::Decode(file, error) => {
+                write!(f, "failed to decode {}: {}", file.display(), error)
+            }
+            AssembleError::Write(file, error) => {
+                write!(f, "failed to write to {}: {}", file, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Re-encodes an existing PNG sequence as a looping gif without rerunning the simulation.
+/// `pattern` names the frames with `{}` standing for the frame number, e.g. `image{}.png`;
+/// every matching file in `frame_dir` is used, in numeric order. `frame_delay` is in
+/// hundredths of a second. All frames must be the size of the first. Returns the number of
+/// frames written.
+pub fn assemble_video(
+    frame_dir: &str,
+    pattern: &str,
+    output: &str,
+    frame_delay: u16,
+) -> Result<usize, AssembleError> {
+    let (prefix, suffix) = pattern
+        .split_once("{}")
+        .ok_or_else(|| AssembleError::InvalidPattern(pattern.to_string()))?;
+    let mut frames = Vec::new();
+    for entry in fs::read_dir(frame_dir).map_err(AssembleError::Io)? {
+        let path = entry.map_err(AssembleError::Io)?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|name| name.strip_suffix(suffix))
+            .and_then(|number| number.parse::<usize>().ok());
+        if let Some(number) = number {
+            frames.push((number, path));
+        }
+    }
+    if frames.is_empty() {
+        return Err(AssembleError::NoFrames {
+            dir: frame_dir.to_string(),
+            pattern: pattern.to_string(),
+        });
+    }
+    frames.sort();
+
+    let mut writer = GifWriter::new(output, frame_delay);
+    let mut expected = None;
+    for (_, path) in &frames {
+        let (size, rgb) = read_rgb_png(path)?;
+        let expected = *expected.get_or_insert(size);
+        if size != expected {
+            return Err(AssembleError::SizeMismatch {
+                file: path.clone(),
+                expected,
+                found: size,
+            });
+        }
+        let side = |pixels: u32| u16::try_from(pixels).ok();
+        let (Some(width), Some(height)) = (side(size.0), side(size.1)) else {
+            return Err(AssembleError::Unsupported(path.clone()));
+        };
+        writer
+            .write_rgb(width, height, &rgb)
+            .map_err(|error| AssembleError::Write(output.to_string(), error))?;
+    }
+    writer
+        .finish()
+        .map_err(|error| AssembleError::Write(output.to_string(), error))?;
+    Ok(frames.len())
+}
+
+/// Decodes a PNG to its size and 8-bit RGB pixels, dropping any alpha channel.
+fn read_rgb_png(path: &Path) -> Result<((u32, u32), Vec<u8>), AssembleError> {
+    let decode_error = |error| AssembleError::Decode(path.to_path_buf(), error);
+    let file = File::open(path).map_err(AssembleError::Io)?;
+    let mut reader = png::Decoder::new(file).read_info().map_err(decode_error)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(decode_error)?;
+    data.truncate(info.buffer_size());
+    let rgb = match (info.color_type, info.bit_depth) {
+        (png::ColorType::Rgb, png::BitDepth::Eight) => data,
+        (png::ColorType::Rgba, png::BitDepth::Eight) => data
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect(),
+        _ => return Err(AssembleError::Unsupported(path.to_path_buf())),
+    };
+    Ok(((info.width, info.height), rgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lgca::Colouring;
+
+    #[test]
+    fn assemble_video_reports_errors_instead_of_panicking() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame_dir = dir.path().to_str().unwrap();
+        let pattern = "image{}.png";
+        let output = dir.path().join("video.gif");
+        let output = output.to_str().unwrap();
+        assert!(matches!(
+            assemble_video(frame_dir, pattern, output, GIF_FRAME_DELAY),
+            Err(AssembleError::NoFrames { .. })
+        ));
+
+        let config = Config::new(8, 4, 2, 1, 1, Colouring::DensityBW);
+        let mut renderer = Renderer::new(&config);
+        let grid = Grid::new(config.width, config.height);
+        for frame in 0..2 {
+            let filename = dir.path().join(format!("image{}.png", frame));
+            renderer.save(&grid, filename.to_str().unwrap()).unwrap();
+        }
+        assert_eq!(
+            assemble_video(frame_dir, pattern, output, GIF_FRAME_DELAY).unwrap(),
+            2
+        );
+        assert!(Path::new(output).exists());
+
+        // The gif's encoder can't be created in a directory that doesn't exist.
+        let missing = dir.path().join("missing").join("video.gif");
+        assert!(matches!(
+            assemble_video(
+                frame_dir,
+                pattern,
+                missing.to_str().unwrap(),
+                GIF_FRAME_DELAY
+            ),
+            Err(AssembleError::Write(..))
+        ));
+    }
+
+    #[test]
+    fn file_sinks_report_write_errors_with_the_filename() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::new(8, 4, 2, 1, 1, Colouring::DensityBW);
+        config.output_dir = dir.path().to_str().unwrap().to_string();
+        let grid = Grid::new(config.width, config.height);
+        CsvSink.write_frame(&grid, &config, 0).unwrap();
+        RawSink.write_frame(&grid, &config, 0).unwrap();
+        let csv = fs::read_to_string(config.output_path("velocity0.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 1 + 4 * 2);
+        assert_eq!(
+            fs::read(config.output_path("grid0.raw")).unwrap(),
+            grid.grid
+        );
+
+        config.output_dir = dir.path().join("missing").to_str().unwrap().to_string();
+        let error = CsvSink.write_frame(&grid, &config, 1).unwrap_err();
+        assert!(error.to_string().contains("velocity1.csv"));
+        let error = RawSink.write_frame(&grid, &config, 1).unwrap_err();
+        assert!(error.to_string().contains("grid1.raw"));
+        let mut stats = VelocityStatsSink::new(&config.output_path("velocity_stats.csv"));
+        let error = stats.write_frame(&grid, &config, 1).unwrap_err();
+        assert!(error.to_string().contains("velocity_stats.csv"));
+    }
+}
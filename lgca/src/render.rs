@@ -0,0 +1,1166 @@
+#![allow(dead_code)]
+
+use std::{
+    f64::consts::PI,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{
+    lgca::{
+        Block, Colouring, Config, Grid, ImageFormat, RenderQuality, ResampleFilter, SummaryMode,
+        VelocityStyle, RGB8,
+    },
+    resample,
+    simulation::Simulation,
+    tracer::TracerSet,
+};
+
+/// Density deviation from the mean at which the pressure colouring saturates.
+const PRESSURE_RANGE: f64 = 0.25;
+
+/// Sector colours for `Colouring::DirectionOctants`, clockwise from up, each sector centred
+/// on its direction. Also used for tracer trails.
+const OCTANT_COLOURS: [RGB8; 8] = [
+    RGB8::new(230, 25, 75),
+    RGB8::new(245, 130, 48),
+    RGB8::new(255, 225, 25),
+    RGB8::new(60, 180, 75),
+    RGB8::new(70, 240, 240),
+    RGB8::new(0, 130, 200),
+    RGB8::new(145, 30, 180),
+    RGB8::new(240, 50, 230),
+];
+
+/// Change in block density between frames at which the activity colouring saturates.
+const ACTIVITY_RANGE: f64 = 0.1;
+
+/// Blocks between arrow centres in the quiver colouring.
+const QUIVER_SPACING: usize = 8;
+
+/// Brightness of the quiver colouring's density background relative to `DensityBW`, dim so
+/// the arrows stand out.
+const QUIVER_BACKGROUND: f64 = 1.0 / 3.0;
+
+/// Turns grids into images: one pixel per `downscale` x `downscale` block, coloured by
+/// `config.colouring`. Built once per output and reused every frame, so the frame and row
+/// buffers are only allocated once.
+pub struct Renderer {
+    config: Config,
+    /// The last frame rendered by `render`, or scratch space for frames drawn whole.
+    frame: Vec<u8>,
+    /// One row of blocks, while streaming.
+    row: Vec<u8>,
+    /// Renders one pixel per cell for `RenderQuality::Supersampled`.
+    cell_renderer: Option<Box<Renderer>>,
+    /// The previous frame, blended into the next one for `config.motion_blur`.
+    previous: Vec<u8>,
+    /// Lookup table for `config.gamma`, or `None` when it is 1.
+    gamma_table: Option<[u8; 256]>,
+    /// Block densities of the previous frame for `Colouring::Activity`, indexed by
+    /// `block_y * blocks_wide + block_x`.
+    previous_density: Vec<f64>,
+    /// Block densities combined over every frame passed to `accumulate`, indexed like
+    /// `previous_density`, with NaN for blocks that were boundary in any of them.
+    accumulated: Vec<f64>,
+    /// `(width, height)` in blocks of `accumulated`.
+    accumulated_size: (usize, usize),
+}
+
+impl Renderer {
+    pub fn new(config: &Config) -> Self {
+        let cell_renderer = match config.render_quality {
+            RenderQuality::Supersampled(_) => Some(Box::new(Renderer::new(&Config {
+                downscale: 1,
+                render_quality: RenderQuality::Block,
+                motion_blur: None,
+                gamma: 1.0,
+                ..config.clone()
+            }))),
+            RenderQuality::Block => None,
+        };
+        Self {
+            config: config.clone(),
+            frame: Vec::new(),
+            row: Vec::new(),
+            cell_renderer,
+            previous: Vec::new(),
+            gamma_table: gamma_table(config.gamma),
+            previous_density: Vec::new(),
+            accumulated: Vec::new(),
+            accumulated_size: (0, 0),
+        }
+    }
+
+    /// Image width and height in pixels for `grid`.
+    pub fn dimensions(&self, grid: &Grid) -> (usize, usize) {
+        (
+            grid.width / self.config.downscale,
+            grid.height / self.config.downscale,
+        )
+    }
+
+    /// Width and height in pixels of saved image files: `dimensions` enlarged by
+    /// `config.output_scale`.
+    pub fn image_dimensions(&self, grid: &Grid) -> (usize, usize) {
+        let (width, height) = self.dimensions(grid);
+        let scale = self.config.output_scale.max(1);
+        (width.saturating_mul(scale), height.saturating_mul(scale))
+    }
+
+    /// Renders the frame and returns its RGB bytes, valid until the next call.
+    pub fn render(&mut self, grid: &Grid) -> &[u8] {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.clear();
+        if !self.render_whole_frame(grid, &mut frame) {
+            let mean_density = self.frame_mean_density(grid);
+            self.render_rows(grid, mean_density, &mut frame);
+        }
+        self.apply_gamma(&mut frame);
+        self.remember_densities(grid);
+        self.frame = frame;
+        &self.frame
+    }
+
+    /// Renders the frame with `colour` choosing each block's pixel instead of
+    /// `config.colouring`, and returns its RGB bytes, valid until the next call. Blocks
+    /// containing boundary cells are passed to `colour` like any other.
+    pub fn render_with(&mut self, grid: &Grid, colour: impl Fn(&Block) -> RGB8) -> &[u8] {
+        self.frame.clear();
+        self.frame.extend(
+            grid.blocks_with_boundary_mode(self.config.downscale, self.config.boundary_mode)
+                .flat_map(|block| colour(&block).as_array()),
+        );
+        let mut frame = std::mem::take(&mut self.frame);
+        self.apply_gamma(&mut frame);
+        self.frame = frame;
+        &self.frame
+    }
+
+    /// Renders the frame with the trail of every tracer drawn over it, each in its own
+    /// colour, and returns its RGB bytes, valid until the next call.
+    pub fn render_with_trails(&mut self, grid: &Grid, tracers: &TracerSet) -> &[u8] {
+        self.render(grid);
+        let mut frame = std::mem::take(&mut self.frame);
+        let scale = self.config.downscale as f64;
+        let to_block = |&(x, y): &(isize, isize)| (x as f64 / scale, y as f64 / scale);
+        for (tracer, colour) in tracers.tracers.iter().zip(OCTANT_COLOURS.iter().cycle()) {
+            for segment in tracer.trail.windows(2) {
+                let (from, to) = (to_block(&segment[0]), to_block(&segment[1]));
+                self.draw_line(&mut frame, grid, from, to, *colour);
+            }
+        }
+        self.frame = frame;
+        &self.frame
+    }
+
+    /// Writes the frame's RGB bytes to `out` one row of blocks at a time, so peak memory is
+    /// bounded by a single row rather than the whole frame (except for the quiver colouring,
+    /// supersampled rendering, motion blur and parallel execution).
+    pub fn write(&mut self, grid: &Grid, out: &mut impl Write) -> io::Result<()> {
+        let mut frame = std::mem::take(&mut self.frame);
+        frame.clear();
+        if self.render_whole_frame(grid, &mut frame) {
+            // Arrows, filter kernels and blending need the whole frame, so these are drawn whole.
+            self.apply_gamma(&mut frame);
+            let result = out.write_all(&frame);
+            self.frame = frame;
+            self.remember_densities(grid);
+            return result;
+        }
+        self.frame = frame;
+        let mut row = std::mem::take(&mut self.row);
+        let mean_density = self.frame_mean_density(grid);
+        let mut result = Ok(());
+        for block_y in 0..grid.height / self.config.downscale {
+            row.clear();
+            self.render_row(block_y, grid, mean_density, &mut row);
+            self.apply_gamma(&mut row);
+            result = out.write_all(&row);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.row = row;
+        self.remember_densities(grid);
+        result
+    }
+
+    /// Writes the frame for an image file: as `write`, enlarged by `config.output_scale`.
+    fn write_image(&mut self, grid: &Grid, out: &mut impl Write) -> io::Result<()> {
+        if self.config.output_scale <= 1 {
+            return self.write(grid, out);
+        }
+        let (width, height) = self.dimensions(grid);
+        let (scale, filter) = (self.config.output_scale, self.config.upscale_filter);
+        let frame = self.render(grid);
+        out.write_all(&resample::upsample(frame, height, width, scale, filter))
+    }
+
+    /// Applies `config.gamma` to every channel of finished pixels.
+    fn apply_gamma(&self, pixels: &mut [u8]) {
+        if let Some(table) = &self.gamma_table {
+            for channel in pixels {
+                *channel = table[*channel as usize];
+            }
+        }
+    }
+
+    /// Keeps the block densities of the frame just drawn for the next activity frame.
+    fn remember_densities(&mut self, grid: &Grid) {
+        if let Colouring::Activity = self.config.colouring {
+            let (blocks_wide, blocks_high) = self.dimensions(grid);
+            let mut densities = vec![0.0; blocks_wide * blocks_high];
+            for block in
+                grid.blocks_with_boundary_mode(self.config.downscale, self.config.boundary_mode)
+            {
+                densities[block.y * blocks_wide + block.x] = block.density();
+            }
+            self.previous_density = densities;
+        }
+    }
+
+    /// Combines the block densities of this frame into the summary buffer by `mode`, for
+    /// `summary_image`. Starts afresh if the frame size has changed.
+    pub fn accumulate(&mut self, grid: &Grid, mode: SummaryMode) {
+        let size = self.dimensions(grid);
+        if self.accumulated_size != size {
+            self.accumulated_size = size;
+            self.accumulated = vec![0.0; size.0 * size.1];
+        }
+        for block in
+            grid.blocks_with_boundary_mode(self.config.downscale, self.config.boundary_mode)
+        {
+            let total = &mut self.accumulated[block.y * size.0 + block.x];
+            *total = match (block.is_boundary(), mode) {
+                (true, _) => f64::NAN,
+                (false, SummaryMode::Max) => total.max(block.density()),
+                (false, SummaryMode::Sum) => *total + block.density(),
+            };
+        }
+    }
+
+    /// The accumulated frames as an RGB image of `dimensions`, grey scaled so the highest
+    /// value is white, whatever the mode or number of frames, and boundary drawn as
+    /// `RGB8::BOUNDARY`. `config.gamma` applies as for frames.
+    pub fn summary_image(&self) -> Vec<u8> {
+        let peak = self
+            .accumulated
+            .iter()
+            .copied()
+            .filter(|value| !value.is_nan())
+            .fold(0.0, f64::max);
+        let mut image: Vec<u8> = self
+            .accumulated
+            .iter()
+            .flat_map(|&value| {
+                let colour = if value.is_nan() {
+                    RGB8::BOUNDARY
+                } else if peak > 0.0 {
+                    let grey = (255.0 * value / peak).round() as u8;
+                    RGB8::new(grey, grey, grey)
+                } else {
+                    RGB8::BLACK
+                };
+                colour.as_array()
+            })
+            .collect();
+        self.apply_gamma(&mut image);
+        image
+    }
+
+    /// Saves `summary_image` as a PNG.
+    pub fn save_summary(&self, filename: &str) -> io::Result<()> {
+        save_png(filename, self.accumulated_size, &self.summary_image())
+    }
+
+    /// Saves the frame in `config.image_format`.
+    pub fn save(&mut self, grid: &Grid, filename: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        match self.config.image_format {
+            ImageFormat::Png => self.write_png(grid, &mut writer)?,
+            ImageFormat::Ppm => self.write_ppm(grid, &mut writer)?,
+            ImageFormat::Bmp => self.write_bmp(grid, &mut writer)?,
+        }
+        writer.flush()
+    }
+
+    fn write_png(&mut self, grid: &Grid, writer: &mut impl Write) -> io::Result<()> {
+        let (width, height) = self.image_dimensions(grid);
+        let mut encoder = png::Encoder::new(
+            writer,
+            header_field(width, "width")?,
+            header_field(height, "height")?,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .expect("Failed to create image writer");
+        let mut stream = writer
+            .stream_writer()
+            .expect("Failed to create image stream");
+        self.write_image(grid, &mut stream)?;
+        stream.finish().map_err(io::Error::other)
+    }
+
+    /// Binary PPM: a short text header followed by the raw RGB bytes. Cheap to write, and
+    /// `ffmpeg` reads it directly.
+    fn write_ppm(&mut self, grid: &Grid, writer: &mut impl Write) -> io::Result<()> {
+        let (width, height) = self.image_dimensions(grid);
+        write!(writer, "P6\n{} {}\n255\n", width, height)?;
+        self.write_image(grid, writer)
+    }
+
+    /// Uncompressed 24-bit BMP, stored top-down (negative height) so rows stream in order.
+    fn write_bmp(&mut self, grid: &Grid, writer: &mut impl Write) -> io::Result<()> {
+        let (width, height) = self.image_dimensions(grid);
+        let padded_row = (3 * width).div_ceil(4) * 4;
+        let image_size = padded_row.saturating_mul(height);
+        let file_size: u32 = header_field(image_size.saturating_add(54), "size")?;
+        writer.write_all(b"BM")?;
+        writer.write_all(&file_size.to_le_bytes())?;
+        writer.write_all(&0_u32.to_le_bytes())?;
+        writer.write_all(&54_u32.to_le_bytes())?;
+        writer.write_all(&40_u32.to_le_bytes())?;
+        writer.write_all(&header_field::<i32>(width, "width")?.to_le_bytes())?;
+        writer.write_all(&(-header_field::<i32>(height, "height")?).to_le_bytes())?;
+        writer.write_all(&1_u16.to_le_bytes())?;
+        writer.write_all(&24_u16.to_le_bytes())?;
+        writer.write_all(&0_u32.to_le_bytes())?;
+        writer.write_all(&(file_size - 54).to_le_bytes())?;
+        writer.write_all(&[0; 16])?;
+        let mut rows = BmpRows {
+            writer,
+            row: Vec::with_capacity(padded_row),
+            row_len: 3 * width,
+            padded_row,
+        };
+        self.write_image(grid, &mut rows)
+    }
+
+    /// Draws the whole frame into `image` when it can't be streamed a row of blocks at a
+    /// time. Returns false, leaving `image` untouched, when it can.
+    fn render_whole_frame(&mut self, grid: &Grid, image: &mut Vec<u8>) -> bool {
+        match (self.config.colouring, self.config.render_quality) {
+            (Colouring::Quiver, _) => self.render_quiver(grid, image),
+            (_, RenderQuality::Supersampled(filter)) => {
+                self.render_supersampled(grid, filter, image)
+            }
+            (_, RenderQuality::Block)
+                if self.config.motion_blur.is_some() || self.renders_in_parallel() =>
+            {
+                let mean_density = self.frame_mean_density(grid);
+                self.render_rows(grid, mean_density, image);
+            }
+            (_, RenderQuality::Block) => return false,
+        }
+        if let Some(alpha) = self.config.motion_blur {
+            self.blend_with_previous(alpha, image);
+        }
+        true
+    }
+
+    /// Blends `image` with the previous frame for motion blur, then keeps it as the new
+    /// previous frame. The first frame has no history and is left as it is.
+    fn blend_with_previous(&mut self, alpha: f64, image: &mut [u8]) {
+        let alpha = alpha.clamp(0.0, 1.0);
+        if self.previous.len() == image.len() {
+            for (current, &previous) in image.iter_mut().zip(&self.previous) {
+                let blended = alpha * *current as f64 + (1.0 - alpha) * previous as f64;
+                *current = blended.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        self.previous.clear();
+        self.previous.extend_from_slice(image);
+    }
+
+    /// Mean grid density for colourings that compare blocks against it, computed once per
+    /// frame.
+    fn frame_mean_density(&self, grid: &Grid) -> f64 {
+        match self.config.colouring {
+            Colouring::Pressure => grid.mean_density(),
+            _ => 0.0,
+        }
+    }
+
+    /// Whether rows are rendered on the rayon thread pool, for `ExecutionMode::Parallel`.
+    #[cfg(feature = "rayon")]
+    fn renders_in_parallel(&self) -> bool {
+        self.config.execution_mode == crate::lgca::ExecutionMode::Parallel
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    fn renders_in_parallel(&self) -> bool {
+        false
+    }
+
+    /// Appends the colours of every row of blocks, top to bottom, to `out`.
+    fn render_rows(&self, grid: &Grid, mean_density: f64, out: &mut Vec<u8>) {
+        #[cfg(feature = "rayon")]
+        if self.renders_in_parallel() {
+            use rayon::prelude::*;
+            let (width, height) = self.dimensions(grid);
+            let row_len = 3 * width;
+            let start = out.len();
+            out.resize(start + row_len * height, 0);
+            if row_len > 0 {
+                out[start..]
+                    .par_chunks_mut(row_len)
+                    .enumerate()
+                    .for_each(|(block_y, pixels)| {
+                        let mut row = Vec::with_capacity(row_len);
+                        self.render_row(block_y, grid, mean_density, &mut row);
+                        pixels.copy_from_slice(&row);
+                    });
+            }
+            return;
+        }
+        for block_y in 0..grid.height / self.config.downscale {
+            self.render_row(block_y, grid, mean_density, out);
+        }
+    }
+
+    /// Appends the colours of one row of blocks, left to right, to `out`.
+    fn render_row(&self, block_y: usize, grid: &Grid, mean_density: f64, out: &mut Vec<u8>) {
+        let blocks_wide = grid.width / self.config.downscale;
+        let colour = |block: &Block| match self.config.colouring {
+            Colouring::DensityBW => self.block_colour_density_bw(block),
+            Colouring::VelocityColour => self.block_colour_velocity_rgb(block),
+            Colouring::Pressure => self.block_colour_pressure(block, mean_density),
+            Colouring::Quiver => self.block_colour_quiver_background(block),
+            Colouring::KineticEnergy => self.block_colour_kinetic_energy(block),
+            Colouring::DirectionOctants => self.block_colour_direction_octants(block),
+            Colouring::Activity => self.block_colour_activity(block, blocks_wide),
+        };
+        self.render_row_with(block_y, grid, &colour, out);
+    }
+
+    fn render_row_with(
+        &self,
+        block_y: usize,
+        grid: &Grid,
+        colour: &impl Fn(&Block) -> RGB8,
+        out: &mut Vec<u8>,
+    ) {
+        for block_x in 0..grid.width / self.config.downscale {
+            let block = Block::with_boundary_mode(
+                block_x,
+                block_y,
+                self.config.downscale,
+                grid,
+                self.config.boundary_mode,
+            );
+            out.extend(colour(&block).as_array());
+        }
+    }
+
+    /// Fraction of the block's cells that are boundary, or `None` when it is drawn as solid
+    /// boundary: wholly boundary, or partly and `config.antialiased_boundary` is off.
+    fn boundary_fraction(&self, block: &Block) -> Option<f64> {
+        let fraction = block.boundary_fraction();
+        if fraction >= 1.0 || (block.is_boundary() && !self.config.antialiased_boundary) {
+            None
+        } else {
+            Some(fraction)
+        }
+    }
+
+    fn block_colour_density_bw(&self, block: &Block) -> RGB8 {
+        let Some(fraction) = self.boundary_fraction(block) else {
+            return RGB8::BOUNDARY;
+        };
+        RGB8::lerp(grey(block.fluid_density()), RGB8::BOUNDARY, fraction)
+    }
+
+    fn block_colour_velocity_rgb(&self, block: &Block) -> RGB8 {
+        if let Some(fraction) = self.boundary_fraction(block) {
+            let (x, y) = block.fluid_velocity();
+            let speed = f64::powf(f64::sqrt((x * x) + (y * y)) / f64::sqrt(2.0), 1.0 / 3.0);
+            let mut angle = f64::atan2(x, y);
+            angle = if angle < 0.0 { angle + 2.0 * PI } else { angle };
+            angle = 180.0 * angle / PI;
+            if !(speed.is_finite() && angle.is_finite()) {
+                // E.g. a zero-sized block; the casts in `from_hsvf64` would turn NaN into garbage.
+                log::debug!(
+                    "non-finite velocity colour (speed {}, angle {}) for {:?}",
+                    speed,
+                    angle,
+                    block
+                );
+                return RGB8::new(0, 0, 0);
+            }
+            let colour = match self.config.velocity_style {
+                VelocityStyle::SaturationAndValue => RGB8::from_hsvf64(angle, speed, speed),
+                VelocityStyle::Value => RGB8::from_hsvf64(angle, 1.0, speed),
+                VelocityStyle::DirectionOnly if speed == 0.0 => RGB8::new(0, 0, 0),
+                VelocityStyle::DirectionOnly => RGB8::from_hsvf64(angle, 1.0, 1.0),
+            };
+            RGB8::lerp(colour, RGB8::BOUNDARY, fraction)
+        } else {
+            RGB8::BOUNDARY
+        }
+    }
+
+    fn block_colour_pressure(&self, block: &Block, mean_density: f64) -> RGB8 {
+        if block.is_boundary() {
+            RGB8::BOUNDARY
+        } else {
+            let t = ((block.density() - mean_density) / PRESSURE_RANGE).clamp(-1.0, 1.0);
+            let fade = (255.0 * (1.0 - t.abs())) as u8;
+            if t >= 0.0 {
+                RGB8::new(255, fade, fade)
+            } else {
+                RGB8::new(fade, fade, 255)
+            }
+        }
+    }
+
+    fn block_colour_kinetic_energy(&self, block: &Block) -> RGB8 {
+        if block.is_boundary() {
+            RGB8::BOUNDARY
+        } else {
+            let (vx, vy) = block.velocity();
+            let energy = 0.5 * (vx * vx + vy * vy);
+            hot_colour(energy / self.config.kinetic_energy_max)
+        }
+    }
+
+    /// Density change since the previous frame, on the same colormap as the kinetic energy.
+    fn block_colour_activity(&self, block: &Block, blocks_wide: usize) -> RGB8 {
+        if block.is_boundary() {
+            return RGB8::BOUNDARY;
+        }
+        let previous = self
+            .previous_density
+            .get(block.y * blocks_wide + block.x)
+            .copied();
+        match previous {
+            Some(previous) => hot_colour((block.density() - previous).abs() / ACTIVITY_RANGE),
+            None => RGB8::new(0, 0, 0),
+        }
+    }
+
+    /// Same speed and angle as `block_colour_velocity_rgb`, with the hue replaced by the
+    /// colour of the angle's sector.
+    fn block_colour_direction_octants(&self, block: &Block) -> RGB8 {
+        if block.is_boundary() {
+            RGB8::BOUNDARY
+        } else {
+            let (x, y) = block.velocity();
+            let speed = f64::powf(f64::hypot(x, y) / f64::sqrt(2.0), 1.0 / 3.0);
+            let angle = f64::atan2(x, y).to_degrees().rem_euclid(360.0);
+            let sector = ((angle + 22.5) / 45.0) as usize % 8;
+            let colour = OCTANT_COLOURS[sector]
+                .as_array()
+                .map(|c| (c as f64 * speed) as u8);
+            RGB8::new(colour[0], colour[1], colour[2])
+        }
+    }
+
+    fn block_colour_quiver_background(&self, block: &Block) -> RGB8 {
+        if block.is_boundary() {
+            RGB8::BOUNDARY
+        } else {
+            grey(QUIVER_BACKGROUND * block.density())
+        }
+    }
+
+    /// Renders one pixel per cell in the configured colouring, then downsamples to one pixel
+    /// per block with `filter`.
+    fn render_supersampled(&mut self, grid: &Grid, filter: ResampleFilter, image: &mut Vec<u8>) {
+        let cell_renderer = self
+            .cell_renderer
+            .as_mut()
+            .expect("supersampled renderer has a cell renderer");
+        let full = cell_renderer.render(grid);
+        // Same layout as `pixel_offset`: `height` rows of `width` pixels.
+        resample::downsample(
+            full,
+            grid.height,
+            grid.width,
+            self.config.downscale,
+            filter,
+            image,
+        );
+    }
+
+    /// Byte offset of a block's pixel in the rendered image.
+    fn pixel_offset(&self, block_x: usize, block_y: usize, grid: &Grid) -> usize {
+        image_offset(block_x, block_y, self.dimensions(grid))
+    }
+
+    /// Rasterizes a line between two points in block coordinates, clipping to the image.
+    fn draw_line(
+        &self,
+        image: &mut [u8],
+        grid: &Grid,
+        from: (f64, f64),
+        to: (f64, f64),
+        colour: RGB8,
+    ) {
+        let (width, height) = self.dimensions(grid);
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let steps = dx.abs().max(dy.abs()).ceil().max(1.0) as usize;
+        for i in 0..=steps {
+            let t = i as f64 / steps as f64;
+            let x = (from.0 + t * dx).round();
+            let y = (from.1 + t * dy).round();
+            let in_bounds = x >= 0.0 && y >= 0.0 && (x as usize) < width && (y as usize) < height;
+            if in_bounds {
+                let offset = self.pixel_offset(x as usize, y as usize, grid);
+                image[offset..offset + 3].copy_from_slice(&colour.as_array());
+            }
+        }
+    }
+
+    /// Draws an arrow from `from` to `to` with a head of two short barbs at the tip.
+    fn draw_arrow(
+        &self,
+        image: &mut [u8],
+        grid: &Grid,
+        from: (f64, f64),
+        to: (f64, f64),
+        colour: RGB8,
+    ) {
+        self.draw_line(image, grid, from, to, colour);
+        let angle = f64::atan2(to.1 - from.1, to.0 - from.0);
+        let barb = 0.3 * f64::hypot(to.0 - from.0, to.1 - from.1);
+        for side in [-1.0, 1.0] {
+            let barb_angle = angle + PI + side * PI / 6.0;
+            let end = (
+                to.0 + barb * barb_angle.cos(),
+                to.1 + barb * barb_angle.sin(),
+            );
+            self.draw_line(image, grid, to, end, colour);
+        }
+    }
+
+    /// Renders the quiver colouring: one arrow per `QUIVER_SPACING` x `QUIVER_SPACING`
+    /// blocks, pointing along the mean velocity there. Lengths are scaled so the fastest
+    /// arrow in the frame spans the spacing.
+    fn render_quiver(&self, grid: &Grid, image: &mut Vec<u8>) {
+        self.render_rows(grid, 0.0, image);
+        let arrow_size = self.config.downscale * QUIVER_SPACING;
+        let mut arrows = Vec::new();
+        for arrow_x in 0..grid.width / arrow_size {
+            for arrow_y in 0..grid.height / arrow_size {
+                let block = Block::with_boundary_mode(
+                    arrow_x,
+                    arrow_y,
+                    arrow_size,
+                    grid,
+                    self.config.boundary_mode,
+                );
+                let (vx, vy) = block.velocity();
+                arrows.push((arrow_x, arrow_y, vx, vy));
+            }
+        }
+        let max_speed = arrows
+            .iter()
+            .map(|&(_, _, vx, vy)| f64::hypot(vx, vy))
+            .fold(0.0, f64::max);
+        if max_speed == 0.0 {
+            return;
+        }
+        let scale = QUIVER_SPACING as f64 / max_speed;
+        let half = QUIVER_SPACING as f64 / 2.0;
+        for (arrow_x, arrow_y, vx, vy) in arrows {
+            let centre = (
+                (arrow_x * QUIVER_SPACING) as f64 + half,
+                (arrow_y * QUIVER_SPACING) as f64 + half,
+            );
+            let from = (centre.0 - vx * scale / 2.0, centre.1 - vy * scale / 2.0);
+            let to = (centre.0 + vx * scale / 2.0, centre.1 + vy * scale / 2.0);
+            self.draw_arrow(image, grid, from, to, RGB8::new(255, 255, 255));
+        }
+    }
+}
+
+/// Lookup table taking each channel value to `255 * (value / 255)^(1 / gamma)`, or `None` for
+/// a gamma of 1, which would leave every value unchanged.
+fn gamma_table(gamma: f64) -> Option<[u8; 256]> {
+    if gamma == 1.0 {
+        return None;
+    }
+    Some(std::array::from_fn(|value| {
+        (255.0 * (value as f64 / 255.0).powf(1.0 / gamma))
+            .round()
+            .clamp(0.0, 255.0) as u8
+    }))
+}
+
+/// "Hot" colormap from black through red and yellow to white as `t` goes from 0 to 1: red,
+/// then green, then blue ramp up in turn. `t` is clamped to \[0, 1].
+fn hot_colour(t: f64) -> RGB8 {
+    let t = 3.0 * t.clamp(0.0, 1.0);
+    let channel = |start: f64| (255.0 * (t - start).clamp(0.0, 1.0)) as u8;
+    RGB8::new(channel(0.0), channel(1.0), channel(2.0))
+}
+
+/// `value` as the integer type an image header stores it in, or an `InvalidInput` error if it
+/// doesn't fit, e.g. a frame more than `u32::MAX` pixels wide for PNG.
+fn header_field<T: TryFrom<usize>>(value: usize, field: &str) -> io::Result<T> {
+    T::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("image {} {} is too large for the file format", field, value),
+        )
+    })
+}
+
+/// Byte offset of pixel `(x, y)` in an RGB image of the given `(width, height)`, in the
+/// layout `Renderer` produces and image formats expect: `height` rows of `width` pixels.
+fn image_offset(x: usize, y: usize, (width, _height): (usize, usize)) -> usize {
+    3 * (y * width + x)
+}
+
+/// Renders several simulations side by side in one image, e.g. the runs of a parameter
+/// sweep, `columns` tiles to a row. Each tile is drawn by its own `Renderer` with that
+/// simulation's config, and is as large as the largest frame; smaller frames are padded with
+/// black.
+pub struct TiledRenderer {
+    columns: usize,
+    renderers: Vec<Renderer>,
+    /// Drawn in the top-left corner of each tile, in order. Missing labels are skipped.
+    labels: Vec<String>,
+    frame: Vec<u8>,
+}
+
+impl TiledRenderer {
+    pub fn new(simulations: &[Simulation], columns: usize) -> Self {
+        Self {
+            columns: columns.max(1),
+            renderers: simulations
+                .iter()
+                .map(|simulation| Renderer::new(&simulation.config))
+                .collect(),
+            labels: Vec::new(),
+            frame: Vec::new(),
+        }
+    }
+
+    /// Labels the tiles, e.g. with the parameter each run uses. Only digits, letters and
+    /// `. - = : _` are drawn, in capitals; anything else shows as `?`.
+    pub fn with_labels(mut self, labels: Vec<String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Size of one tile in pixels: the largest frame of any of the simulations.
+    fn tile_dimensions(&self, simulations: &[Simulation]) -> (usize, usize) {
+        self.renderers
+            .iter()
+            .zip(simulations)
+            .map(|(renderer, simulation)| renderer.dimensions(&simulation.grid))
+            .fold((0, 0), |(width, height), (w, h)| {
+                (width.max(w), height.max(h))
+            })
+    }
+
+    /// Image width and height in pixels.
+    pub fn dimensions(&self, simulations: &[Simulation]) -> (usize, usize) {
+        let (tile_width, tile_height) = self.tile_dimensions(simulations);
+        let rows = self.renderers.len().div_ceil(self.columns);
+        (self.columns * tile_width, rows * tile_height)
+    }
+
+    /// Renders every simulation's current frame into its tile and returns the RGB bytes of
+    /// the whole image, valid until the next call. `simulations` must be the ones the
+    /// renderer was built for, in the same order.
+    pub fn render(&mut self, simulations: &[Simulation]) -> &[u8] {
+        assert_eq!(
+            simulations.len(),
+            self.renderers.len(),
+            "TiledRenderer was built for a different number of simulations"
+        );
+        let (tile_width, tile_height) = self.tile_dimensions(simulations);
+        let dimensions = self.dimensions(simulations);
+        self.frame.clear();
+        self.frame.resize(3 * dimensions.0 * dimensions.1, 0);
+        for (index, (renderer, simulation)) in
+            self.renderers.iter_mut().zip(simulations).enumerate()
+        {
+            let origin = (
+                (index % self.columns) * tile_width,
+                (index / self.columns) * tile_height,
+            );
+            let tile_dimensions = renderer.dimensions(&simulation.grid);
+            let tile = renderer.render(&simulation.grid);
+            for y in 0..tile_dimensions.1 {
+                for x in 0..tile_dimensions.0 {
+                    let from = image_offset(x, y, tile_dimensions);
+                    let to = image_offset(origin.0 + x, origin.1 + y, dimensions);
+                    self.frame[to..to + 3].copy_from_slice(&tile[from..from + 3]);
+                }
+            }
+            if let Some(label) = self.labels.get(index) {
+                draw_text(
+                    &mut self.frame,
+                    dimensions,
+                    (origin.0 + 1, origin.1 + 1),
+                    label,
+                );
+            }
+        }
+        &self.frame
+    }
+
+    /// Renders the current frames and saves them as one PNG.
+    pub fn save(&mut self, simulations: &[Simulation], filename: &str) -> io::Result<()> {
+        let dimensions = self.dimensions(simulations);
+        let image_data = self.render(simulations);
+        save_png(filename, dimensions, image_data)
+    }
+}
+
+/// Saves a PNG of where the boundary is, ignoring the particles: white for boundary and black
+/// for fluid, one pixel per `config.downscale` block, with blocks that are partly boundary in
+/// proportion. For checking obstacle geometry before running anything.
+pub fn save_boundary_map(grid: &Grid, config: &Config, filename: &str) -> io::Result<()> {
+    let mut renderer = Renderer::new(&Config {
+        gamma: 1.0,
+        ..config.clone()
+    });
+    let dimensions = renderer.dimensions(grid);
+    let image_data = renderer.render_with(grid, |block| grey(block.boundary_fraction()));
+    save_png(filename, dimensions, image_data)
+}
+
+/// Grey at `level` in \[0, 1], e.g. a block density, scaled to the full 0 to 255 range.
+fn grey(level: f64) -> RGB8 {
+    let value = (255.0 * level.clamp(0.0, 1.0)).round() as u8;
+    RGB8::new(value, value, value)
+}
+
+/// Writes RGB bytes of the given `(width, height)` to a new PNG file.
+fn save_png(filename: &str, (width, height): (usize, usize), image_data: &[u8]) -> io::Result<()> {
+    let file = File::create(filename)?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        header_field(width, "width")?,
+        header_field(height, "height")?,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(image_data)?;
+    Ok(())
+}
+
+/// Draws `text` in white with a 3x5 pixel font, starting at pixel `origin` and clipped to
+/// the image.
+fn draw_text(image: &mut [u8], dimensions: (usize, usize), origin: (usize, usize), text: &str) {
+    for (index, character) in text.chars().enumerate() {
+        for (row, bits) in glyph(character).into_iter().enumerate() {
+            for column in 0..3 {
+                let (x, y) = (origin.0 + 4 * index + column, origin.1 + row);
+                if bits & (0b100 >> column) != 0 && x < dimensions.0 && y < dimensions.1 {
+                    let offset = image_offset(x, y, dimensions);
+                    image[offset..offset + 3].copy_from_slice(&[255, 255, 255]);
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a character in a 3x5 pixel font, top to bottom, leftmost pixel in the high bit.
+fn glyph(character: char) -> [u8; 5] {
+    match character.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        ' ' => [0; 5],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// Regroups an RGB byte stream into BMP rows: BGR order, padded to a multiple of 4 bytes.
+struct BmpRows<'a, W: Write> {
+    writer: &'a mut W,
+    row: Vec<u8>,
+    row_len: usize,
+    padded_row: usize,
+}
+
+impl<W: Write> Write for BmpRows<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.row.push(byte);
+            if self.row.len() == self.row_len {
+                for pixel in self.row.chunks_mut(3) {
+                    pixel.swap(0, 2);
+                }
+                self.row.resize(self.padded_row, 0);
+                self.writer.write_all(&self.row)?;
+                self.row.clear();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lgca::{cell, BoundaryMode, UpscaleFilter};
+
+    #[test]
+    fn header_fields_are_checked_at_the_cast_boundary() {
+        assert_eq!(
+            header_field::<u32>(u32::MAX as usize, "width").unwrap(),
+            u32::MAX
+        );
+        assert_eq!(
+            header_field::<i32>(i32::MAX as usize, "width").unwrap(),
+            i32::MAX
+        );
+        assert!(header_field::<i32>(i32::MAX as usize + 1, "width").is_err());
+        if let Some(too_wide) = (u32::MAX as usize).checked_add(1) {
+            assert!(header_field::<u32>(too_wide, "width").is_err());
+        }
+    }
+
+    #[test]
+    fn non_square_frames_are_row_major() {
+        let config = Config::new(6, 3, 1, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.set(5, 0, cell::UP);
+        grid.set(1, 2, cell::FULL);
+        let mut renderer = Renderer::new(&config);
+        assert_eq!(renderer.dimensions(&grid), (6, 3));
+        let frame = renderer.render(&grid).to_vec();
+        assert_eq!(frame.len(), 3 * 6 * 3);
+        let pixel = |x: usize, y: usize| &frame[3 * (y * 6 + x)..3 * (y * 6 + x) + 3];
+        assert_eq!(pixel(5, 0), [64, 64, 64]);
+        assert_eq!(pixel(1, 2), [255, 255, 255]);
+        let lit = frame.chunks(3).filter(|pixel| pixel != &[0, 0, 0]).count();
+        assert_eq!(lit, 2);
+
+        let mut streamed = Vec::new();
+        renderer.write(&grid, &mut streamed).unwrap();
+        assert_eq!(streamed, frame);
+        let blocks =
+            renderer.render_with(&grid, |block| RGB8::new(block.x as u8, block.y as u8, 0));
+        let expected: Vec<u8> = (0..3)
+            .flat_map(|y| (0..6).flat_map(move |x| [x, y, 0]))
+            .collect();
+        assert_eq!(blocks, expected);
+    }
+
+    #[test]
+    fn velocity_styles_keep_the_hue_of_slow_blocks() {
+        let mut grid = Grid::new(4, 4);
+        grid.set(1, 1, cell::RIGHT);
+        let colour = |grid: &Grid, style| {
+            let mut config = Config::new(4, 4, 4, 1, 1, Colouring::VelocityColour);
+            config.velocity_style = style;
+            let pixel = Renderer::new(&config).render(grid).to_vec();
+            (
+                pixel[0].min(pixel[1]).min(pixel[2]),
+                pixel[0].max(pixel[1]).max(pixel[2]),
+            )
+        };
+        // A single particle in a 4x4 block is slow: dim, and washed out unless saturation is
+        // held at 1.
+        let (darkest, brightest) = colour(&grid, VelocityStyle::SaturationAndValue);
+        assert!(darkest > 0 && brightest < 128);
+        let (darkest, brightest) = colour(&grid, VelocityStyle::Value);
+        assert!(darkest == 0 && brightest < 128);
+        assert_eq!(colour(&grid, VelocityStyle::DirectionOnly), (0, 255));
+        grid.set(1, 1, cell::EMPTY);
+        assert_eq!(colour(&grid, VelocityStyle::DirectionOnly), (0, 0));
+    }
+
+    #[test]
+    fn boundary_map_shows_walls_only() {
+        let config = Config::new(8, 4, 2, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, 8, 4, 1.0);
+        grid.set_boundary(crate::lgca::Region::new(0, 0, 2, 2));
+        grid.set(4, 0, cell::BOUNDARY);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("boundary.png");
+        let filename = path.to_str().unwrap();
+        save_boundary_map(&grid, &config, filename).unwrap();
+        let decoder = png::Decoder::new(File::open(filename).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (4, 2));
+        let grey: Vec<u8> = pixels.chunks(3).map(|pixel| pixel[0]).collect();
+        assert_eq!(grey, [255, 0, 64, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn antialiased_boundary_blends_partial_blocks() {
+        let mut config = Config::new(8, 4, 4, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, 8, 4, 1.0);
+        grid.set_boundary(crate::lgca::Region::new(0, 0, 1, 4));
+        assert_eq!(
+            Renderer::new(&config).render(&grid),
+            [0, 0, 0, 255, 255, 255]
+        );
+        // A quarter of the left block is wall: three quarters of the full fluid's grey.
+        config.antialiased_boundary = true;
+        assert_eq!(
+            Renderer::new(&config).render(&grid),
+            [191, 191, 191, 255, 255, 255]
+        );
+        // Particles reflecting in the wall cells don't count towards the fluid's density.
+        for y in 0..4 {
+            grid.set(0, y, cell::BOUNDARY | cell::FULL);
+        }
+        assert_eq!(
+            Renderer::new(&config).render(&grid),
+            [191, 191, 191, 255, 255, 255]
+        );
+        grid.set_boundary(crate::lgca::Region::new(0, 0, 4, 4));
+        assert_eq!(Renderer::new(&config).render(&grid)[..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn output_scale_enlarges_saved_images() {
+        let mut config = Config::new(8, 4, 4, 1, 1, Colouring::DensityBW);
+        config.output_scale = 3;
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(4, 0, 4, 4, 1.0);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scaled.png");
+        let filename = path.to_str().unwrap();
+        let read_grey = |filename: &str| {
+            let decoder = png::Decoder::new(File::open(filename).unwrap());
+            let mut reader = decoder.read_info().unwrap();
+            let mut pixels = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut pixels).unwrap();
+            assert_eq!((reader.info().width, reader.info().height), (6, 3));
+            pixels
+                .chunks(3)
+                .take(6)
+                .map(|pixel| pixel[0])
+                .collect::<Vec<u8>>()
+        };
+        Renderer::new(&config).save(&grid, filename).unwrap();
+        assert_eq!(read_grey(filename), [0, 0, 0, 255, 255, 255]);
+        config.upscale_filter = UpscaleFilter::Bilinear;
+        Renderer::new(&config).save(&grid, filename).unwrap();
+        assert_eq!(read_grey(filename), [0, 0, 85, 170, 255, 255]);
+    }
+
+    #[test]
+    fn periodic_grids_render_without_edge_walls() {
+        let mut config = Config::new(12, 8, 4, 1, 1, Colouring::DensityBW);
+        config.boundary_mode = BoundaryMode::Periodic;
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.5);
+        let mut renderer = Renderer::new(&config);
+        let boundary = RGB8::BOUNDARY.as_array();
+        assert!(renderer
+            .render(&grid)
+            .chunks(3)
+            .all(|pixel| pixel != boundary));
+        let render_with = renderer.render_with(&grid, |block| {
+            if block.is_boundary() {
+                RGB8::BOUNDARY
+            } else {
+                RGB8::new(255, 255, 255)
+            }
+        });
+        assert!(render_with.chunks(3).all(|pixel| pixel != boundary));
+    }
+
+    #[test]
+    fn summary_combines_every_frame() {
+        let config = Config::new(12, 4, 4, 1, 1, Colouring::DensityBW);
+        let mut first = Grid::new(config.width, config.height);
+        first.fill_region(0, 0, 4, 4, 1.0);
+        first.set(11, 3, cell::BOUNDARY);
+        let mut second = Grid::new(config.width, config.height);
+        second.fill_region(0, 0, 8, 4, 1.0);
+        let grey = |renderer: &Renderer| -> Vec<u8> {
+            renderer
+                .summary_image()
+                .chunks(3)
+                .map(|pixel| pixel[0])
+                .collect()
+        };
+        let mut max = Renderer::new(&config);
+        let mut sum = Renderer::new(&config);
+        for grid in [&first, &second] {
+            max.accumulate(grid, SummaryMode::Max);
+            sum.accumulate(grid, SummaryMode::Sum);
+        }
+        // The last block was boundary in the first frame, so it stays boundary.
+        assert_eq!(grey(&max), [255, 255, 0]);
+        assert_eq!(grey(&sum), [255, 128, 0]);
+    }
+
+    #[test]
+    fn gamma_table_brightens_midtones_and_keeps_the_ends() {
+        assert!(gamma_table(1.0).is_none());
+        let table = gamma_table(2.2).unwrap();
+        assert_eq!((table[0], table[255]), (0, 255));
+        assert_eq!(table[128], 186);
+        assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn tiled_renderer_saves_one_png_and_reports_write_errors() {
+        let config = Config::new(8, 4, 2, 1, 1, Colouring::DensityBW);
+        let simulations: Vec<Simulation> = (0..3)
+            .map(|_| Simulation::new(config.clone(), Grid::new(8, 4), Vec::new()))
+            .collect();
+        let mut tiled = TiledRenderer::new(&simulations, 2);
+        assert_eq!(tiled.dimensions(&simulations), (8, 4));
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("tiles.png");
+        tiled
+            .save(&simulations, filename.to_str().unwrap())
+            .unwrap();
+        assert!(filename.exists());
+        let missing = dir.path().join("missing").join("tiles.png");
+        assert!(tiled.save(&simulations, missing.to_str().unwrap()).is_err());
+    }
+}
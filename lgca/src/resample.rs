@@ -0,0 +1,172 @@
+#![allow(dead_code)]
+
+use std::f64::consts::PI;
+
+use crate::lgca::{ResampleFilter, UpscaleFilter};
+
+impl ResampleFilter {
+    /// Half-width of the kernel, in output pixels.
+    fn support(self) -> f64 {
+        match self {
+            ResampleFilter::Box => 0.5,
+            ResampleFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Kernel weight at `t` output pixels from the sample centre.
+    fn weight(self, t: f64) -> f64 {
+        match self {
+            ResampleFilter::Box => {
+                if t.abs() <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ResampleFilter::Lanczos3 => {
+                if t == 0.0 {
+                    1.0
+                } else if t.abs() < 3.0 {
+                    let x = PI * t;
+                    3.0 * x.sin() * (x / 3.0).sin() / (x * x)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// Normalized `(input index, weight)` taps for each output sample when shrinking an axis of
+/// `input_len` samples by `factor`. Taps past the edge are clamped to the edge sample.
+fn taps(input_len: usize, factor: usize, filter: ResampleFilter) -> Vec<Vec<(usize, f32)>> {
+    let radius = (filter.support() * factor as f64).ceil() as isize;
+    (0..input_len / factor)
+        .map(|i| {
+            let centre = (i as f64 + 0.5) * factor as f64;
+            let first = centre.floor() as isize - radius;
+            let mut taps: Vec<(usize, f64)> = (first..=first + 2 * radius)
+                .map(|j| {
+                    let t = (j as f64 + 0.5 - centre) / factor as f64;
+                    let index = j.clamp(0, input_len as isize - 1) as usize;
+                    (index, filter.weight(t))
+                })
+                .filter(|&(_, weight)| weight != 0.0)
+                .collect();
+            let total: f64 = taps.iter().map(|&(_, weight)| weight).sum();
+            for tap in taps.iter_mut() {
+                tap.1 /= total;
+            }
+            taps.into_iter()
+                .map(|(index, weight)| (index, weight as f32))
+                .collect()
+        })
+        .collect()
+}
+
+/// Shrinks an RGB image of `rows` rows by `columns` pixels by `factor` along both axes,
+/// replacing the contents of `out` with `rows / factor` rows of `columns / factor` pixels.
+pub fn downsample(
+    image: &[u8],
+    rows: usize,
+    columns: usize,
+    factor: usize,
+    filter: ResampleFilter,
+    out: &mut Vec<u8>,
+) {
+    let out_rows = rows / factor;
+    let out_columns = columns / factor;
+    let column_taps = taps(columns, factor, filter);
+    let row_taps = taps(rows, factor, filter);
+
+    // Shrink each row first, keeping full precision for the second pass.
+    let mut narrow = vec![0.0_f32; rows * out_columns * 3];
+    for row in 0..rows {
+        let input = &image[3 * row * columns..3 * (row + 1) * columns];
+        let output = &mut narrow[3 * row * out_columns..3 * (row + 1) * out_columns];
+        for (pixel, taps) in output.chunks_exact_mut(3).zip(&column_taps) {
+            for &(index, weight) in taps {
+                for channel in 0..3 {
+                    pixel[channel] += weight * input[3 * index + channel] as f32;
+                }
+            }
+        }
+    }
+
+    out.clear();
+    out.resize(out_rows * out_columns * 3, 0);
+    for (row, taps) in row_taps.iter().enumerate() {
+        let mut sum = vec![0.0_f32; out_columns * 3];
+        for &(index, weight) in taps {
+            let input = &narrow[3 * index * out_columns..3 * (index + 1) * out_columns];
+            for (total, &value) in sum.iter_mut().zip(input) {
+                *total += weight * value;
+            }
+        }
+        let output = &mut out[3 * row * out_columns..3 * (row + 1) * out_columns];
+        for (byte, value) in output.iter_mut().zip(sum) {
+            *byte = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// `(first input index, second input index, weight of the second)` for each output sample
+/// when enlarging an axis of `input_len` samples by `factor`, interpolating between sample
+/// centres and holding the edge samples past the outermost centres.
+fn linear_taps(input_len: usize, factor: usize) -> Vec<(usize, usize, f32)> {
+    (0..input_len * factor)
+        .map(|i| {
+            let position =
+                ((i as f64 + 0.5) / factor as f64 - 0.5).clamp(0.0, input_len as f64 - 1.0);
+            let first = position.floor() as usize;
+            let second = (first + 1).min(input_len - 1);
+            (first, second, (position - first as f64) as f32)
+        })
+        .collect()
+}
+
+/// Enlarges an RGB image of `rows` rows by `columns` pixels by `factor` along both axes,
+/// returning `rows * factor` rows of `columns * factor` pixels.
+pub fn upsample(
+    image: &[u8],
+    rows: usize,
+    columns: usize,
+    factor: usize,
+    filter: UpscaleFilter,
+) -> Vec<u8> {
+    let out_columns = columns * factor;
+    let mut out = Vec::with_capacity(3 * rows * factor * out_columns);
+    if rows == 0 || columns == 0 {
+        return out;
+    }
+    match filter {
+        UpscaleFilter::Nearest => {
+            for row in image.chunks_exact(3 * columns) {
+                let wide: Vec<u8> = row
+                    .chunks_exact(3)
+                    .flat_map(|pixel| pixel.repeat(factor))
+                    .collect();
+                for _ in 0..factor {
+                    out.extend_from_slice(&wide);
+                }
+            }
+        }
+        UpscaleFilter::Bilinear => {
+            let column_taps = linear_taps(columns, factor);
+            for (top, bottom, t) in linear_taps(rows, factor) {
+                let top = &image[3 * top * columns..3 * (top + 1) * columns];
+                let bottom = &image[3 * bottom * columns..3 * (bottom + 1) * columns];
+                for &(left, right, u) in &column_taps {
+                    for channel in 0..3 {
+                        let sample = |row: &[u8], x: usize| row[3 * x + channel] as f32;
+                        let upper = sample(top, left) * (1.0 - u) + sample(top, right) * u;
+                        let lower = sample(bottom, left) * (1.0 - u) + sample(bottom, right) * u;
+                        let value = upper * (1.0 - t) + lower * t;
+                        out.push(value.round().clamp(0.0, 255.0) as u8);
+                    }
+                }
+            }
+        }
+    }
+    out
+}
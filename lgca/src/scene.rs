@@ -0,0 +1,299 @@
+#![allow(dead_code)]
+
+use std::{fmt, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::lgca::{Config, Grid, Region, Source};
+
+/// An experiment definition read from a TOML or JSON file: the initial fluid, the obstacles,
+/// and the sources and sinks. Coordinates are in cells.
+///
+/// ```toml
+/// wall_thickness = 1
+///
+/// [[fluid]]
+/// x = 0
+/// y = 0
+/// width = 1024
+/// height = 512
+/// density = 0.25
+///
+/// [[obstacles]]
+/// shape = "circle"
+/// x = 256.0
+/// y = 256.0
+/// radius = 40.0
+///
+/// [[sources]]
+/// x = 1
+/// y = 1
+/// width = 8
+/// height = 510
+/// density = 0.5
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    /// Thickness of the wall around the domain edge; 0 leaves the edges open.
+    pub wall_thickness: usize,
+    pub fluid: Vec<FluidRegion>,
+    pub obstacles: Vec<Obstacle>,
+    pub sources: Vec<SourceRegion>,
+    /// Regions emptied every step.
+    pub sinks: Vec<SinkRegion>,
+}
+
+/// A region filled with random particles at the start of the run.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FluidRegion {
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+    pub density: f64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "shape", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Obstacle {
+    Rectangle {
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+    },
+    Circle {
+        x: f64,
+        y: f64,
+        radius: f64,
+    },
+    /// Vertices as `[x, y]` pairs, closed automatically.
+    Polygon {
+        points: Vec<(f64, f64)>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SourceRegion {
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+    pub density: f64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SinkRegion {
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// The file extension is neither `.toml` nor `.json`.
+    UnknownFormat(String),
+    /// Entry `index` of the scene's `kind` list (fluid, obstacles, sources or sinks) reaches
+    /// outside the grid.
+    OutOfBounds {
+        kind: &'static str,
+        index: usize,
+    },
+    /// `wall_thickness` leaves no fluid between opposite walls.
+    WallTooThick(usize),
+    /// Obstacle `index` is a polygon with fewer than 3 vertices, or one that isn't finite or
+    /// lies outside the grid.
+    InvalidPolygon(usize),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "failed to read scene: {}", error),
+            SceneError::Toml(error) => write!(f, "invalid scene: {}", error),
+            SceneError::Json(error) => write!(f, "invalid scene: {}", error),
+            SceneError::UnknownFormat(filename) => write!(
+                f,
+                "unknown scene format for {} (expected .toml or .json)",
+                filename
+            ),
+            SceneError::OutOfBounds { kind, index } => {
+                write!(f, "{} entry {} reaches outside the grid", kind, index)
+            }
+            SceneError::WallTooThick(thickness) => write!(
+                f,
+                "wall_thickness {} leaves no fluid inside the grid",
+                thickness
+            ),
+            SceneError::InvalidPolygon(index) => write!(
+                f,
+                "obstacle {} needs at least 3 finite vertices inside the grid",
+                index
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl FluidRegion {
+    fn region(&self) -> Region {
+        Region::new(self.x, self.y, self.width, self.height)
+    }
+}
+
+impl SourceRegion {
+    fn region(&self) -> Region {
+        Region::new(self.x, self.y, self.width, self.height)
+    }
+}
+
+impl SinkRegion {
+    fn region(&self) -> Region {
+        Region::new(self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Whether every cell of `region` is inside `grid`.
+fn fits(grid: &Grid, region: Region) -> bool {
+    region.clamp_to(grid.width, grid.height) == region
+}
+
+/// The first of `regions` that reaches outside `grid`, as an error naming its `kind`.
+fn check_regions(
+    grid: &Grid,
+    kind: &'static str,
+    mut regions: impl Iterator<Item = Region>,
+) -> Result<(), SceneError> {
+    match regions.position(|region| !fits(grid, region)) {
+        Some(index) => Err(SceneError::OutOfBounds { kind, index }),
+        None => Ok(()),
+    }
+}
+
+impl Scene {
+    /// Reads a scene, choosing TOML or JSON by the file extension.
+    pub fn load(filename: &str) -> Result<Self, SceneError> {
+        let text = fs::read_to_string(filename).map_err(SceneError::Io)?;
+        match Path::new(filename).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(SceneError::Toml),
+            Some("json") => serde_json::from_str(&text).map_err(SceneError::Json),
+            _ => Err(SceneError::UnknownFormat(filename.to_string())),
+        }
+    }
+
+    /// Checks every region, the wall thickness and the polygons against the grid, so `apply`
+    /// can't panic part-way through.
+    pub fn validate(&self, grid: &Grid) -> Result<(), SceneError> {
+        let fluid = self.fluid.iter().map(FluidRegion::region);
+        let sources = self.sources.iter().map(SourceRegion::region);
+        let sinks = self.sinks.iter().map(SinkRegion::region);
+        check_regions(grid, "fluid", fluid)?;
+        check_regions(grid, "sources", sources)?;
+        check_regions(grid, "sinks", sinks)?;
+        if 2 * self.wall_thickness >= grid.width.min(grid.height) {
+            return Err(SceneError::WallTooThick(self.wall_thickness));
+        }
+        let inside = |&(x, y): &(f64, f64)| {
+            (0.0..=grid.width as f64).contains(&x) && (0.0..=grid.height as f64).contains(&y)
+        };
+        for (index, obstacle) in self.obstacles.iter().enumerate() {
+            match obstacle {
+                Obstacle::Rectangle {
+                    x,
+                    y,
+                    width,
+                    height,
+                } if !fits(grid, Region::new(*x, *y, *width, *height)) => {
+                    return Err(SceneError::OutOfBounds {
+                        kind: "obstacles",
+                        index,
+                    });
+                }
+                Obstacle::Polygon { points } if points.len() < 3 || !points.iter().all(inside) => {
+                    return Err(SceneError::InvalidPolygon(index));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the initial grid: fluid first, then the edge wall and obstacles on top of it.
+    /// Returns the sources and sinks to pass to the simulation, or the first problem
+    /// `validate` finds, before the grid is touched.
+    pub fn apply(&self, grid: &mut Grid, config: &Config) -> Result<Vec<Source>, SceneError> {
+        self.validate(grid)?;
+        for fluid in &self.fluid {
+            grid.fill(fluid.region(), fluid.density);
+        }
+        if self.wall_thickness > 0 {
+            grid.set_boundary_at_edge(config, self.wall_thickness);
+        }
+        for obstacle in &self.obstacles {
+            match obstacle {
+                Obstacle::Rectangle {
+                    x,
+                    y,
+                    width,
+                    height,
+                } => grid.set_boundary(Region::new(*x, *y, *width, *height)),
+                Obstacle::Circle { x, y, radius } => grid.fill_boundary_circle(*x, *y, *radius),
+                Obstacle::Polygon { points } => grid.fill_boundary_polygon(points),
+            }
+        }
+        let sources = self
+            .sources
+            .iter()
+            .map(|source| Source::from_region(source.region(), source.density));
+        let sinks = self
+            .sinks
+            .iter()
+            .map(|sink| Source::from_region(sink.region(), 0.0));
+        Ok(sources.chain(sinks).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lgca::Colouring;
+
+    #[test]
+    fn apply_rejects_scenes_that_do_not_fit_the_grid() {
+        let config = Config::new(16, 8, 1, 1, 1, Colouring::DensityBW);
+        let apply = |text: &str| {
+            let scene: Scene = toml::from_str(text).unwrap();
+            scene.apply(&mut Grid::new(config.width, config.height), &config)
+        };
+        let sources = apply(
+            "wall_thickness = 1\n\
+             [[sources]]\nx = 1\ny = 1\nwidth = 2\nheight = 6\ndensity = 0.5\n\
+             [[obstacles]]\nshape = \"polygon\"\npoints = [[4, 2], [8, 2], [6, 6]]",
+        );
+        assert_eq!(sources.unwrap().len(), 1);
+        assert!(matches!(
+            apply("[[sinks]]\nx = 12\ny = 0\nwidth = 8\nheight = 8"),
+            Err(SceneError::OutOfBounds {
+                kind: "sinks",
+                index: 0
+            })
+        ));
+        assert!(matches!(
+            apply("wall_thickness = 4"),
+            Err(SceneError::WallTooThick(4))
+        ));
+        assert!(matches!(
+            apply("[[obstacles]]\nshape = \"polygon\"\npoints = [[4, 2], [20, 2], [6, 6]]"),
+            Err(SceneError::InvalidPolygon(0))
+        ));
+    }
+}
@@ -0,0 +1,531 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    apply_sponges, boundary_force,
+    checkpoint::{save_checkpoint, Checkpoint},
+    collision_count,
+    lgca::{CollisionMode, Config, ConfigError, ExecutionMode, Grid, Region, Source},
+    obstacle::MovingObstacle,
+    output::{build_sinks, in_file},
+    propagate, propagate_inplace, save_final_state, save_frame, tick,
+    tracer::TracerSet,
+    update_sources, RunClock,
+};
+
+/// Mixed into `Config::seed` for the source stream, so it differs from a grid fill seeded
+/// with the same value.
+const SOURCE_STREAM: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Mixed into `Config::seed` for the sponge stream, so adding a sponge doesn't change what the
+/// sources inject.
+const SPONGE_STREAM: u64 = 0xbf58_476d_1ce4_e5b9;
+
+/// Mixed into `Config::seed` for the stream of `CollisionMode::HppPlusRandomization`.
+const COLLISION_STREAM: u64 = 0x94d0_49bb_1331_11eb;
+
+/// Mixed into `Config::seed` for the stream refilling the cells moving obstacles leave.
+const OBSTACLE_STREAM: u64 = 0xd6e8_feb8_6659_fd93;
+
+/// A random stream for `Simulation`: `seed` mixed with `stream`, or from the operating
+/// system's entropy without a seed.
+fn stream_rng(seed: Option<u64>, stream: u64) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed ^ stream),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Receives the grid and step number of every written frame; see `Simulation::frame_callback`.
+pub type FrameCallback = Box<dyn FnMut(&Grid, usize)>;
+
+/// The state of a run: both halves of the double buffer, the sources, and the step count.
+pub struct Simulation {
+    pub config: Config,
+    pub grid: Grid,
+    next_grid: Grid,
+    pub sources: Vec<Source>,
+    pub step: usize,
+    /// Index of the last frame written.
+    pub frame: usize,
+    /// `(step, fx, fy)` for every step, recorded when `config.log_forces` is set.
+    pub forces: Vec<(usize, i64, i64)>,
+    /// One entry per diagnostics sample, recorded when `config.log_frames` is set.
+    pub frame_log: Vec<FrameRecord>,
+    /// Called with the grid and step number every time a frame is written, frame 0 included, for
+    /// custom diagnostics or live output without changing the crate.
+    pub frame_callback: Option<FrameCallback>,
+    /// Net particles added by the sources since the run started (or resumed). Without sponges,
+    /// moving obstacles or open edges, `grid.total_particles()` changes by exactly this much.
+    pub injected_particles: i64,
+    /// Tagged particles followed every step; see `TracerSet`.
+    pub tracers: TracerSet,
+    /// Boundaries moved to their trajectory position at the start of every step.
+    pub obstacles: Vec<MovingObstacle>,
+    steady_state: Option<SteadyStateDetector>,
+    /// The step at which steady state was detected, when `config.steady_state_tolerance` is set.
+    pub steady_state_step: Option<usize>,
+    /// Set from another thread (e.g. a Ctrl-C handler) to make `run_to_completion` stop after
+    /// the current step, with a final frame and a checkpoint.
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Draws the sources' random injection, seeded from `config.seed`.
+    source_rng: StdRng,
+    /// Draws the sponges' scattering, seeded from `config.seed`.
+    sponge_rng: StdRng,
+    /// Draws the randomizing collisions, seeded from `config.seed`.
+    collision_rng: StdRng,
+    /// Draws the fluid refilling cells the moving obstacles leave, seeded from `config.seed`.
+    obstacle_rng: StdRng,
+}
+
+impl Simulation {
+    pub fn new(config: Config, grid: Grid, sources: Vec<Source>) -> Self {
+        // Stepping in place never touches the second buffer, so it stays empty.
+        let next_grid = if config.in_place_propagation {
+            Grid::new(0, 0)
+        } else {
+            Grid::new(grid.width, grid.height)
+        };
+        let steady_state = config
+            .steady_state_tolerance
+            .map(|tolerance| SteadyStateDetector::new(tolerance, config.steady_state_window));
+        let source_rng = stream_rng(config.seed, SOURCE_STREAM);
+        let sponge_rng = stream_rng(config.seed, SPONGE_STREAM);
+        let collision_rng = stream_rng(config.seed, COLLISION_STREAM);
+        let obstacle_rng = stream_rng(config.seed, OBSTACLE_STREAM);
+        Self {
+            config,
+            grid,
+            next_grid,
+            sources,
+            step: 0,
+            frame: 0,
+            forces: Vec::new(),
+            frame_log: Vec::new(),
+            frame_callback: None,
+            injected_particles: 0,
+            tracers: TracerSet::default(),
+            obstacles: Vec::new(),
+            steady_state,
+            steady_state_step: None,
+            interrupt: None,
+            source_rng,
+            sponge_rng,
+            collision_rng,
+            obstacle_rng,
+        }
+    }
+
+    /// Continues a run from a checkpoint, keeping its step and frame numbering. The
+    /// checkpoint's grid must be the size `config` describes.
+    pub fn resume(
+        config: Config,
+        checkpoint: Checkpoint,
+        sources: Vec<Source>,
+    ) -> Result<Self, ResumeError> {
+        let grid = &checkpoint.grid;
+        if (grid.width, grid.height) != (config.width, config.height) {
+            return Err(ResumeError {
+                checkpoint: (grid.width, grid.height),
+                config: (config.width, config.height),
+            });
+        }
+        let mut simulation = Self::new(config, checkpoint.grid, sources);
+        simulation.step = checkpoint.step;
+        simulation.frame = checkpoint.frame;
+        Ok(simulation)
+    }
+
+    pub fn save_checkpoint(&self, filename: &str) -> io::Result<()> {
+        save_checkpoint(filename, self.step, self.frame, &self.grid).map_err(in_file(filename))
+    }
+
+    /// Advances the physics by one step: moving obstacles, sources, sponges, then streaming and
+    /// collision. No frames are rendered and no progress is reported; see `tick` for that.
+    pub fn advance(&mut self) {
+        for obstacle in &mut self.obstacles {
+            obstacle.update(&mut self.grid, self.step, &mut self.obstacle_rng);
+        }
+        self.injected_particles +=
+            update_sources(&mut self.grid, &self.sources, &mut self.source_rng);
+        apply_sponges(&mut self.grid, &self.config.sponges, &mut self.sponge_rng);
+        self.tracers.advance(&self.grid);
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!(
+                "step {}: {} collisions",
+                self.step + 1,
+                collision_count(&self.grid)
+            );
+        }
+        let in_place = self.config.in_place_propagation;
+        if in_place {
+            propagate_inplace(&mut self.grid, &self.config);
+        } else {
+            propagate(&self.grid, &mut self.next_grid, &self.config);
+        }
+        if let CollisionMode::HppPlusRandomization { rate } = self.config.collision_mode {
+            let stepped = if in_place {
+                &mut self.grid
+            } else {
+                &mut self.next_grid
+            };
+            let (width, height) = (stepped.width, stepped.height);
+            stepped.apply_sponge(
+                Region::new(0, 0, width, height),
+                rate,
+                &mut self.collision_rng,
+            );
+        }
+        self.step += 1;
+        let warmed_up = self.step > self.config.warmup_steps;
+        if self.config.log_forces && warmed_up {
+            let (fx, fy) = boundary_force(&self.grid, &self.next_grid);
+            self.forces.push((self.step, fx, fy));
+        }
+        if !in_place {
+            self.grid.swap_buffers(&mut self.next_grid);
+        }
+        if let Some(detector) = self.steady_state.as_mut().filter(|_| warmed_up) {
+            let (px, py) = grid_momentum(&self.grid, self.config.execution_mode);
+            let speed = (px as f64).hypot(py as f64) / self.grid.grid.len() as f64;
+            if detector.push(speed) && self.steady_state_step.is_none() {
+                self.steady_state_step = Some(self.step);
+            }
+        }
+    }
+
+    /// Runs the remaining `config.warmup_steps`, e.g. before writing frame 0.
+    pub fn warm_up(&mut self) {
+        self.step_many(self.config.warmup_steps.saturating_sub(self.step));
+    }
+
+    /// Advances the physics by `n` steps.
+    pub fn step_many(&mut self, n: usize) {
+        for _ in 0..n {
+            self.advance();
+        }
+    }
+
+    /// Runs to `config.iterations` (or steady state, the wall time limit or an interrupt) with
+    /// every output the config asks for: the warm-up and frame 0 for a new run, frames,
+    /// checkpoints and progress as it goes, and the force and frame logs at the end. Returns
+    /// the final grid and a summary of the run, or the first error writing any of the output.
+    pub fn run_to_completion(mut self) -> Result<(Grid, RunStats), RunError> {
+        self.config.validate().map_err(RunError::InvalidConfig)?;
+        std::fs::create_dir_all(&self.config.output_dir)
+            .map_err(in_file(&self.config.output_dir))
+            .map_err(RunError::Io)?;
+        let mut sinks = build_sinks(&self.config);
+        if self.step == 0 {
+            if self.config.warmup_steps > 0 {
+                log::info!("warming up for {} steps", self.config.warmup_steps);
+                self.warm_up();
+            }
+            save_frame(&mut self, &mut sinks).map_err(RunError::Io)?;
+        }
+        let config = &self.config;
+        log::info!(
+            "running {}x{} grid from step {} to {}: downscale {}, {:?} colouring, frameskip \
+             {:?}, output to {}",
+            config.width,
+            config.height,
+            self.step,
+            config.iterations,
+            config.downscale,
+            config.colouring,
+            config.frameskip,
+            config.output_dir
+        );
+        let mut clock = RunClock::new(self.step);
+        let mut stopped_early = None;
+        while self.step < self.config.iterations && self.steady_state_step.is_none() {
+            tick(&mut self, &mut sinks, &mut clock).map_err(RunError::Io)?;
+            let interrupted = self
+                .interrupt
+                .as_ref()
+                .is_some_and(|flag| flag.load(Ordering::SeqCst));
+            let over_time = self
+                .config
+                .max_wall_time
+                .filter(|&limit| clock.start_time.elapsed() >= limit);
+            if interrupted {
+                stopped_early = Some(StopReason::Interrupted { step: self.step });
+            } else if let Some(limit) = over_time {
+                stopped_early = Some(StopReason::WallTime {
+                    limit,
+                    step: self.step,
+                });
+            }
+            if stopped_early.is_some() {
+                save_final_state(&mut self, &mut sinks, &clock).map_err(RunError::Io)?;
+                break;
+            }
+        }
+        for sink in sinks.iter_mut() {
+            sink.finish().map_err(RunError::Io)?;
+        }
+        if self.config.log_forces {
+            self.save_forces(&self.config.output_path("forces.csv"))
+                .map_err(RunError::Io)?;
+        }
+        if self.config.log_frames {
+            self.save_frame_log(&self.config.output_path("frames.csv"))
+                .map_err(RunError::Io)?;
+        }
+        if self.config.show_progress {
+            eprintln!();
+        }
+        let stats = RunStats {
+            steps: self.step,
+            last_frame: self.frame,
+            elapsed: clock.start_time.elapsed(),
+            steady_state_step: self.steady_state_step,
+            stopped_early,
+            occupancy: self.grid.occupancy_histogram(),
+        };
+        Ok((self.grid, stats))
+    }
+
+    /// Writes the recorded boundary forces as `step,fx,fy` rows.
+    pub fn save_forces(&self, filename: &str) -> io::Result<()> {
+        let write = || {
+            let mut writer = BufWriter::new(File::create(filename)?);
+            writeln!(writer, "step,fx,fy")?;
+            for (step, fx, fy) in &self.forces {
+                writeln!(writer, "{},{},{}", step, fx, fy)?;
+            }
+            writer.flush()
+        };
+        write().map_err(in_file(filename))
+    }
+
+    /// Samples the diagnostics for a frame just saved, unless `config.diagnostics_stride` gives
+    /// them their own cadence. Called whenever a frame is saved.
+    pub fn record_frame(&mut self) {
+        if self.config.diagnostics_stride.is_none() {
+            self.record_diagnostics();
+        }
+    }
+
+    /// Records the state of the grid against the current step and frame index, if
+    /// `config.log_frames` is set, and logs it at debug level.
+    pub fn record_diagnostics(&mut self) {
+        if !self.config.log_frames && !log::log_enabled!(log::Level::Debug) {
+            return;
+        }
+        let record = FrameRecord {
+            frame: self.frame,
+            step: self.step,
+            time: self.physical_time(),
+            particles: grid_particles(&self.grid, self.config.execution_mode),
+            momentum: grid_momentum(&self.grid, self.config.execution_mode),
+        };
+        log::debug!(
+            "frame {} at step {} (t = {:.3}): {} particles, momentum {:?}",
+            record.frame,
+            record.step,
+            record.time,
+            record.particles,
+            record.momentum
+        );
+        if self.config.log_frames {
+            self.frame_log.push(record);
+        }
+    }
+
+    /// Writes the recorded frames as `frame,step,time,particles,px,py` rows.
+    pub fn save_frame_log(&self, filename: &str) -> io::Result<()> {
+        let write = || {
+            let mut writer = BufWriter::new(File::create(filename)?);
+            writeln!(writer, "frame,step,time,particles,px,py")?;
+            for record in &self.frame_log {
+                let (px, py) = record.momentum;
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{}",
+                    record.frame, record.step, record.time, record.particles, px, py
+                )?;
+            }
+            writer.flush()
+        };
+        write().map_err(in_file(filename))
+    }
+
+    /// Elapsed simulated time, `step * dt`.
+    pub fn physical_time(&self) -> f64 {
+        self.step as f64 * self.config.dt
+    }
+}
+
+/// Whole-grid particle count for the diagnostics, summed in parallel in
+/// `ExecutionMode::Parallel`.
+#[cfg(feature = "rayon")]
+fn grid_particles(grid: &Grid, mode: ExecutionMode) -> u64 {
+    match mode {
+        ExecutionMode::Serial => grid.total_particles(),
+        ExecutionMode::Parallel => grid.par_total_particles(),
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn grid_particles(grid: &Grid, _mode: ExecutionMode) -> u64 {
+    grid.total_particles()
+}
+
+/// Whole-grid momentum for the diagnostics, summed in parallel in `ExecutionMode::Parallel`.
+#[cfg(feature = "rayon")]
+fn grid_momentum(grid: &Grid, mode: ExecutionMode) -> (i64, i64) {
+    match mode {
+        ExecutionMode::Serial => crate::total_momentum(grid),
+        ExecutionMode::Parallel => grid.par_momentum(),
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn grid_momentum(grid: &Grid, _mode: ExecutionMode) -> (i64, i64) {
+    crate::total_momentum(grid)
+}
+
+/// Summary of a run from `Simulation::run_to_completion`.
+#[derive(Clone, Debug)]
+pub struct RunStats {
+    /// The step the run ended at.
+    pub steps: usize,
+    /// Index of the last frame written; frames 0 to this one are in the output.
+    pub last_frame: usize,
+    /// Wall-clock time of the run loop, excluding the warm-up.
+    pub elapsed: Duration,
+    pub steady_state_step: Option<usize>,
+    /// Why the run ended before `config.iterations` without reaching steady state, if it did.
+    pub stopped_early: Option<StopReason>,
+    /// Fluid cells by particle count 0-4 at the end; see `Grid::occupancy_histogram`.
+    pub occupancy: [u64; 5],
+}
+
+/// Why a run stopped early. Either way a final frame and a checkpoint were written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Interrupted { step: usize },
+    WallTime { limit: Duration, step: usize },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Interrupted { step } => write!(f, "interrupted at step {}", step),
+            StopReason::WallTime { limit, step } => {
+                write!(f, "wall time limit of {:?} reached at step {}", limit, step)
+            }
+        }
+    }
+}
+
+/// Why `Simulation::run_to_completion` could not start or finish.
+#[derive(Debug)]
+pub enum RunError {
+    InvalidConfig(Vec<ConfigError>),
+    /// The output directory could not be created, or an output could not be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::InvalidConfig(errors) => {
+                write!(f, "invalid config: ")?;
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+            RunError::Io(error) => write!(f, "failed to write the output: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// `Simulation::resume` was given a checkpoint whose grid is not the size of the config's.
+#[derive(Debug)]
+pub struct ResumeError {
+    /// Width and height of the checkpoint's grid.
+    pub checkpoint: (usize, usize),
+    /// Width and height in the config.
+    pub config: (usize, usize),
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "checkpoint grid is {}x{} but the config is for {}x{}",
+            self.checkpoint.0, self.checkpoint.1, self.config.0, self.config.1
+        )
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+/// The state of the grid at a diagnostics sample, with the index of the last frame saved so
+/// images can be tied back to the run.
+pub struct FrameRecord {
+    pub frame: usize,
+    pub step: usize,
+    /// Physical time, `step * dt`.
+    pub time: f64,
+    pub particles: u64,
+    /// Net momentum `(px, py)` of the fluid cells.
+    pub momentum: (i64, i64),
+}
+
+/// Watches a global metric over a sliding window of steps and reports when it has settled.
+pub struct SteadyStateDetector {
+    tolerance: f64,
+    window: usize,
+    history: VecDeque<f64>,
+}
+
+impl SteadyStateDetector {
+    pub fn new(tolerance: f64, window: usize) -> Self {
+        Self {
+            tolerance,
+            window,
+            history: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Records the latest value. Returns true once the last `window` values all lie within
+    /// `tolerance` of each other.
+    pub fn push(&mut self, value: f64) -> bool {
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(value);
+        if self.history.len() < self.window {
+            return false;
+        }
+        let (min, max) = self
+            .history
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &value| {
+                (min.min(value), max.max(value))
+            });
+        max - min <= self.tolerance
+    }
+}
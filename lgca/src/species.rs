@@ -0,0 +1,229 @@
+#![allow(dead_code)]
+
+use crate::lgca::{cell, Cell, CellExt, Grid};
+
+/// Particle species for two-species runs. Species are tracked in a second layer alongside the
+/// main grid: a direction bit set in the species layer means the particle moving that way is
+/// `B`; a particle whose bit is clear is `A`. Boundary bits in the species layer are ignored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Species {
+    A,
+    B,
+}
+
+/// What happens when two particles meet head-on in a cell.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PairRule {
+    /// The HPP rule: the pair leaves at right angles, rotated clockwise.
+    Scatter,
+    /// Both particles reverse, so the pair bounces off each other.
+    Reflect,
+}
+
+/// Collision rule for each pair of species.
+///
+/// Species bits always travel with their particles, so the particle count of each species is
+/// conserved exactly. Both rules conserve total momentum (a head-on pair carries none), but the
+/// momentum of a single species is not conserved by either.
+pub struct SpeciesRules {
+    pub a_a: PairRule,
+    pub b_b: PairRule,
+    pub a_b: PairRule,
+}
+
+impl SpeciesRules {
+    pub fn rule(&self, first: Species, second: Species) -> PairRule {
+        match (first, second) {
+            (Species::A, Species::A) => self.a_a,
+            (Species::B, Species::B) => self.b_b,
+            _ => self.a_b,
+        }
+    }
+}
+
+impl Default for SpeciesRules {
+    /// Immiscible fluids: like species scatter, unlike species repel.
+    fn default() -> Self {
+        Self {
+            a_a: PairRule::Scatter,
+            b_b: PairRule::Scatter,
+            a_b: PairRule::Reflect,
+        }
+    }
+}
+
+fn species_of(species_mask: Cell, direction: Cell) -> Species {
+    if species_mask & direction == 0 {
+        Species::A
+    } else {
+        Species::B
+    }
+}
+
+/// Species-aware `resolve_collisions`, returning the new cell and species mask.
+pub fn resolve_collisions_species(
+    cell_value: Cell,
+    species_mask: Cell,
+    rules: &SpeciesRules,
+) -> (Cell, Cell) {
+    let species_mask = species_mask & cell_value & cell::FULL;
+    if cell_value.is_boundary() {
+        return (
+            crate::resolve_collisions(cell_value),
+            cell::opposite(species_mask),
+        );
+    }
+    match cell_value {
+        0b0101 | 0b1010 => {
+            let first = species_of(species_mask, cell_value & (cell::UP | cell::RIGHT));
+            let second = species_of(species_mask, cell_value & (cell::DOWN | cell::LEFT));
+            match rules.rule(first, second) {
+                PairRule::Scatter => (
+                    cell::rotate_clockwise(cell_value),
+                    cell::rotate_clockwise(species_mask),
+                ),
+                PairRule::Reflect => (cell_value, cell::opposite(species_mask)),
+            }
+        }
+        other => (other, species_mask),
+    }
+}
+
+/// Two-species counterpart of `propagate_grid`, streaming the species layer with the particles.
+pub fn propagate_species(
+    grid: &Grid,
+    species: &Grid,
+    next_grid: &mut Grid,
+    next_species: &mut Grid,
+    rules: &SpeciesRules,
+) {
+    for y in 0..grid.height as isize {
+        for x in 0..grid.width as isize {
+            let gather = |layer: &Grid| {
+                cell::DIRECTIONS
+                    .into_iter()
+                    .zip(cell::NEIGHBOR_OFFSETS)
+                    .fold(cell::EMPTY, |incoming, (direction, (dx, dy))| {
+                        incoming | (layer.get(x + dx, y + dy) & cell::opposite(direction))
+                    })
+            };
+            let (next_state, next_mask) = if !grid.get(x, y).is_boundary() {
+                resolve_collisions_species(gather(grid), gather(species), rules)
+            } else {
+                // Species bits follow their particles off the wall.
+                let fluid = crate::fluid_neighbours(grid, x, y);
+                (
+                    crate::reflect_at_wall(gather(grid), fluid) | cell::BOUNDARY,
+                    crate::reflect_at_wall(gather(species) & gather(grid), fluid),
+                )
+            };
+            next_grid.set(x, y, next_state);
+            next_species.set(x, y, next_mask);
+        }
+    }
+}
+
+/// Marks every particle in the region as `species`.
+pub fn fill_species_region(
+    grid: &Grid,
+    species: &mut Grid,
+    x_min: isize,
+    y_min: isize,
+    width: usize,
+    height: usize,
+    kind: Species,
+) {
+    for y in y_min..y_min + height as isize {
+        for x in x_min..x_min + width as isize {
+            let mask = match kind {
+                Species::A => cell::EMPTY,
+                Species::B => grid.get(x, y) & cell::FULL,
+            };
+            species.set(x, y, mask);
+        }
+    }
+}
+
+/// Total number of particles of the given species.
+pub fn count_species(grid: &Grid, species: &Grid, kind: Species) -> usize {
+    grid.grid
+        .iter()
+        .zip(&species.grid)
+        .map(|(&value, &mask)| {
+            let particles = value & cell::FULL;
+            match kind {
+                Species::A => (particles & !mask).count_ones(),
+                Species::B => (particles & mask).count_ones(),
+            }
+        })
+        .sum::<u32>() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lgca::{Colouring, Config, TraversalOrder};
+
+    fn mixed_box() -> (Config, Grid, Grid) {
+        let config = Config::new(48, 32, 4, 10, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.fill_region(0, 0, config.width, config.height, 0.4);
+        grid.set_boundary_at_edge(&config, 1);
+        let mut species = Grid::new(config.width, config.height);
+        fill_species_region(&grid, &mut species, 24, 0, 24, 32, Species::B);
+        (config, grid, species)
+    }
+
+    #[test]
+    fn single_species_matches_hpp() {
+        let (config, grid, _) = mixed_box();
+        let species = Grid::new(config.width, config.height);
+        let mut expected = Grid::new(config.width, config.height);
+        let mut next_grid = Grid::new(config.width, config.height);
+        let mut next_species = Grid::new(config.width, config.height);
+        crate::propagate_grid(&grid, &mut expected, &TraversalOrder::RowMajor);
+        propagate_species(
+            &grid,
+            &species,
+            &mut next_grid,
+            &mut next_species,
+            &SpeciesRules::default(),
+        );
+        assert_eq!(next_grid.grid, expected.grid);
+        assert!(next_species.grid.iter().all(|&mask| mask == cell::EMPTY));
+    }
+
+    #[test]
+    fn species_counts_are_conserved() {
+        let (config, mut grid, mut species) = mixed_box();
+        let count_a = count_species(&grid, &species, Species::A);
+        let count_b = count_species(&grid, &species, Species::B);
+        let mut next_grid = Grid::new(config.width, config.height);
+        let mut next_species = Grid::new(config.width, config.height);
+        for _ in 0..20 {
+            propagate_species(
+                &grid,
+                &species,
+                &mut next_grid,
+                &mut next_species,
+                &SpeciesRules::default(),
+            );
+            grid.swap_buffers(&mut next_grid);
+            species.swap_buffers(&mut next_species);
+        }
+        assert_eq!(count_species(&grid, &species, Species::A), count_a);
+        assert_eq!(count_species(&grid, &species, Species::B), count_b);
+    }
+
+    #[test]
+    fn unlike_pair_reflects() {
+        let rules = SpeciesRules::default();
+        let (next, mask) = resolve_collisions_species(cell::UP | cell::DOWN, cell::UP, &rules);
+        assert_eq!(next, cell::UP | cell::DOWN);
+        assert_eq!(mask, cell::DOWN);
+        let (next, mask) =
+            resolve_collisions_species(cell::UP | cell::DOWN, cell::UP | cell::DOWN, &rules);
+        assert_eq!(next, cell::RIGHT | cell::LEFT);
+        assert_eq!(mask, cell::RIGHT | cell::LEFT);
+    }
+}
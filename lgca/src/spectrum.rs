@@ -0,0 +1,109 @@
+#![allow(dead_code)]
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use crate::lgca::{Block, Grid};
+
+/// Mean velocity of every block, in `block_y * width + block_x` order. Blocks containing
+/// boundary cells are set to zero so walls don't add spurious energy.
+pub struct VelocityField {
+    pub width: usize,
+    pub height: usize,
+    pub vx: Vec<f64>,
+    pub vy: Vec<f64>,
+}
+
+impl VelocityField {
+    pub fn from_grid(grid: &Grid, block_size: usize) -> Self {
+        let width = grid.width / block_size;
+        let height = grid.height / block_size;
+        let mut vx = Vec::with_capacity(width * height);
+        let mut vy = Vec::with_capacity(width * height);
+        for block_y in 0..height {
+            for block_x in 0..width {
+                let block = Block::new(block_x, block_y, block_size, grid);
+                let (block_vx, block_vy) = if block.is_boundary() {
+                    (0.0, 0.0)
+                } else {
+                    block.velocity()
+                };
+                vx.push(block_vx);
+                vy.push(block_vy);
+            }
+        }
+        Self {
+            width,
+            height,
+            vx,
+            vy,
+        }
+    }
+}
+
+/// In-place 2D FFT of a `width` x `height` field stored row by row.
+fn fft_2d(data: &mut [Complex<f64>], width: usize, height: usize) {
+    let mut planner = FftPlanner::new();
+    planner.plan_fft_forward(width).process(data);
+    let mut column = vec![Complex::default(); height];
+    let column_fft = planner.plan_fft_forward(height);
+    for x in 0..width {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = data[y * width + x];
+        }
+        column_fft.process(&mut column);
+        for (y, value) in column.iter().enumerate() {
+            data[y * width + x] = *value;
+        }
+    }
+}
+
+/// Signed mode number of FFT index `i` along an axis of length `n`.
+fn mode(i: usize, n: usize) -> f64 {
+    if i <= n / 2 {
+        i as f64
+    } else {
+        i as f64 - n as f64
+    }
+}
+
+/// Kinetic-energy spectrum `E(k)` of the field: the energy of all Fourier modes in the shell
+/// `k - 0.5 <= |k| < k + 0.5`, for `k = 0..=min(width, height) / 2`. Wavenumbers are mode
+/// numbers (cycles per domain length), exact for a square field. The shells sum to the mean
+/// kinetic energy per block, `0.5 * mean(vx^2 + vy^2)`, up to the modes beyond the last shell.
+pub fn energy_spectrum(field: &VelocityField) -> Vec<f64> {
+    let (width, height) = (field.width, field.height);
+    let transform = |values: &[f64]| {
+        let mut data: Vec<_> = values.iter().map(|&v| Complex::new(v, 0.0)).collect();
+        fft_2d(&mut data, width, height);
+        data
+    };
+    let vx = transform(&field.vx);
+    let vy = transform(&field.vy);
+    let norm = ((width * height) as f64).powi(2);
+    let mut spectrum = vec![0.0; width.min(height) / 2 + 1];
+    for ky in 0..height {
+        for kx in 0..width {
+            let k = f64::hypot(mode(kx, width), mode(ky, height)).round() as usize;
+            if let Some(shell) = spectrum.get_mut(k) {
+                let i = ky * width + kx;
+                *shell += 0.5 * (vx[i].norm_sqr() + vy[i].norm_sqr()) / norm;
+            }
+        }
+    }
+    spectrum
+}
+
+/// Writes the spectrum as `k,energy` rows.
+pub fn save_energy_spectrum(filename: &str, spectrum: &[f64]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(writer, "k,energy")?;
+    for (k, energy) in spectrum.iter().enumerate() {
+        writeln!(writer, "{},{}", k, energy)?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,167 @@
+#![allow(dead_code)]
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::lgca::{cell, Cell, CellExt, Grid};
+
+/// One tagged particle and the cells it has visited.
+pub struct Tracer {
+    pub x: isize,
+    pub y: isize,
+    /// The direction bit of the tagged particle.
+    pub direction: Cell,
+    /// The step the particle was tagged at; `trail[i]` is its cell at `start_step + i`.
+    pub start_step: usize,
+    pub trail: Vec<(isize, isize)>,
+    /// False once the particle has been lost, after which the trail stops growing.
+    pub active: bool,
+}
+
+/// Follows a few individually tagged particles through the run, for drawing trails.
+///
+/// Particles in the lattice gas are indistinguishable, so identity through a collision is a
+/// convention. The rule used here: a particle that leaves a fluid cell with its direction
+/// unchanged keeps its tag; when a head-on pair scatters, the tag passes to the outgoing
+/// particle 90 degrees clockwise of the tagged one's incoming direction (the two tags of a
+/// pair never land on the same particle); in a boundary cell, the tag follows the particle's
+/// own reflection off the wall. A tracer is lost if its particle disappears, e.g. when a
+/// source or sponge rewrites its cell.
+#[derive(Default)]
+pub struct TracerSet {
+    pub tracers: Vec<Tracer>,
+}
+
+impl TracerSet {
+    /// Tags the particle at `(x, y)` moving in `direction`, at the current `step`. Returns
+    /// false, tagging nothing, if there is no such particle.
+    pub fn tag(&mut self, grid: &Grid, x: isize, y: isize, direction: Cell, step: usize) -> bool {
+        if grid.get(x, y) & direction == 0 || direction.count_ones() != 1 {
+            return false;
+        }
+        self.tracers.push(Tracer {
+            x,
+            y,
+            direction,
+            start_step: step,
+            trail: vec![(x, y)],
+            active: true,
+        });
+        true
+    }
+
+    /// Moves every tracer through one step of `propagate_grid` applied to `grid`, which must
+    /// be the grid about to be propagated (after sources and sponges).
+    pub fn advance(&mut self, grid: &Grid) {
+        for tracer in self.tracers.iter_mut().filter(|tracer| tracer.active) {
+            if grid.get(tracer.x, tracer.y) & tracer.direction == 0 {
+                tracer.active = false;
+                continue;
+            }
+            let index = cell::DIRECTIONS
+                .iter()
+                .position(|&direction| direction == tracer.direction)
+                .expect("tracer direction is a single direction bit");
+            let (dx, dy) = cell::NEIGHBOR_OFFSETS[index];
+            // Across the edge of the grid, the particle bounces straight back into its cell.
+            let (x, y) = match grid.try_get(tracer.x + dx, tracer.y + dy) {
+                Some(_) => (tracer.x + dx, tracer.y + dy),
+                None => {
+                    tracer.direction = cell::opposite(tracer.direction);
+                    (tracer.x, tracer.y)
+                }
+            };
+            let value = grid.get(x, y);
+            tracer.direction = if value.is_boundary() {
+                crate::reflect_at_wall(tracer.direction, crate::fluid_neighbours(grid, x, y))
+            } else if crate::resolve_collisions(crate::gather_incoming(grid, x, y))
+                & tracer.direction
+                == 0
+            {
+                cell::rotate_clockwise(tracer.direction)
+            } else {
+                tracer.direction
+            };
+            (tracer.x, tracer.y) = (x, y);
+            tracer.trail.push((x, y));
+        }
+    }
+
+    /// Writes every trail as `tracer,step,x,y` rows.
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writeln!(writer, "tracer,step,x,y")?;
+        for (index, tracer) in self.tracers.iter().enumerate() {
+            for (offset, (x, y)) in tracer.trail.iter().enumerate() {
+                writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    index,
+                    tracer.start_step + offset,
+                    x,
+                    y
+                )?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lgca::{Colouring, Config, TraversalOrder};
+
+    #[test]
+    fn tracer_follows_its_particle_through_a_collision_and_a_wall() {
+        let config = Config::new(8, 8, 1, 1, 1, Colouring::DensityBW);
+        let mut grid = Grid::new(config.width, config.height);
+        grid.set_boundary_at_edge(&config, 1);
+        // A head-on pair meets at (3, 3), then the right-moving particle turns down.
+        grid.set(2, 3, cell::RIGHT);
+        grid.set(4, 3, cell::LEFT);
+        let mut tracers = TracerSet::default();
+        assert!(tracers.tag(&grid, 2, 3, cell::RIGHT, 0));
+        assert!(!tracers.tag(&grid, 2, 3, cell::UP, 0));
+        let mut next = Grid::new(config.width, config.height);
+        for _ in 0..4 {
+            tracers.advance(&grid);
+            crate::propagate_grid(&grid, &mut next, &TraversalOrder::RowMajor);
+            grid.swap_buffers(&mut next);
+            let tracer = &tracers.tracers[0];
+            assert!(grid.get(tracer.x, tracer.y) & tracer.direction != 0);
+        }
+        // Into the bottom wall at y = 0 and back out.
+        assert_eq!(
+            tracers.tracers[0].trail,
+            [(2, 3), (3, 3), (3, 2), (3, 1), (3, 0)]
+        );
+        assert_eq!(tracers.tracers[0].direction, cell::UP);
+    }
+
+    #[test]
+    fn tracer_bounces_back_off_the_edge_of_the_grid() {
+        let mut grid = Grid::new(4, 4);
+        grid.set(3, 1, cell::RIGHT);
+        let mut tracers = TracerSet::default();
+        assert!(tracers.tag(&grid, 3, 1, cell::RIGHT, 0));
+        let mut next = Grid::new(4, 4);
+        tracers.advance(&grid);
+        crate::propagate_grid(&grid, &mut next, &TraversalOrder::RowMajor);
+        grid.swap_buffers(&mut next);
+        let tracer = &tracers.tracers[0];
+        assert!(tracer.active);
+        assert_eq!((tracer.x, tracer.y, tracer.direction), (3, 1, cell::LEFT));
+        assert_eq!(grid.get(3, 1), cell::LEFT);
+
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("tracers.csv");
+        tracers.save(filename.to_str().unwrap()).unwrap();
+        let rows = std::fs::read_to_string(&filename).unwrap();
+        assert_eq!(rows, "tracer,step,x,y\n0,0,3,1\n0,1,3,1\n");
+        let missing = dir.path().join("missing").join("tracers.csv");
+        assert!(tracers.save(missing.to_str().unwrap()).is_err());
+    }
+}